@@ -1,7 +1,33 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let proto_files = ["proto/infernece.proto", "proto/gate.proto"];
+    let proto_files = ["proto/infernece.proto", "proto/gate.proto", "proto/verifier.proto"];
     let includes = ["proto"];
-    tonic_build::configure().compile(&proto_files, &includes)?;
+    tonic_build::configure()
+        .type_attribute(
+            ".aizel.InferenceRequest",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        // bodies of the JSON endpoints of the verifier server
+        .type_attribute(
+            ".aizel.VerifyReportRequest",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .type_attribute(
+            ".aizel.VerifyReportResponse",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            ".aizel.VerifyInferenceRequest",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .type_attribute(
+            ".aizel.VerificationCheck",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            ".aizel.VerifyInferenceResponse",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .compile(&proto_files, &includes)?;
     // tonic_build::compile_protos("proto/infernece.proto")?;
     // tonic_build::compile_protos("proto/gate.proto")?;
     Ok(())
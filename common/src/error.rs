@@ -37,6 +37,14 @@ pub enum Error {
     ContractError { message: String },
     #[error("NetworkConfigNotFoundError: 'network' not found")]
     NetworkConfigNotFoundError { network: String },
+    #[error("ModelIntegrityError '{model}': {message}")]
+    ModelIntegrityError { model: String, message: String },
+    #[error("ModelServerError '{server}': {message}\n{log_tail}")]
+    ModelServerError {
+        server: String,
+        message: String,
+        log_tail: String,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -57,6 +65,14 @@ pub enum VerificationError {
     },
     #[error("TDXVerificationError {message}")]
     TDXVerificationError { message: String },
+    #[error("SevSnpVerificationError {message}")]
+    SevSnpVerificationError { message: String },
+    #[error("EventLogError {message}")]
+    EventLogError { message: String },
+    #[error("ReportDataError {message}")]
+    ReportDataError { message: String },
+    #[error("PolicyViolationError {}", .violations.join("; "))]
+    PolicyViolationError { violations: Vec<String> },
 }
 
 #[derive(Error, Debug)]
@@ -0,0 +1,72 @@
+use crate::error::Error;
+use crate::tee::TEEType;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use std::path::Path;
+use std::time::Duration;
+
+pub const GCP_METADATA_URL: &str = "http://metadata.google.internal/computeMetadata/v1/instance/";
+pub const ALICLOUD_METADATA_URL: &str = "http://100.100.100.200/latest/meta-data";
+pub const SEV_GUEST_DEVICE: &str = "/dev/sev-guest";
+pub const DETECT_TIMEOUT_MILLIS: u64 = 1000;
+
+async fn probe(client: &Client, url: &str, headers: HeaderMap) -> Result<(), String> {
+    let response = client
+        .get(url)
+        .headers(headers)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Detects the cloud TEE from the metadata server answering, GCP first, then falls back to an
+/// SEV-SNP guest device for bare metal hosts. Each probe gets `timeout`, so detection fails fast
+/// on hosts where the metadata addresses hang.
+pub async fn detect_tee_type(timeout: Duration) -> Result<TEEType, Error> {
+    let client = Client::builder()
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .build()
+        .map_err(|e| Error::UnkownTEETypeERROR {
+            message: e.to_string(),
+        })?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Metadata-Flavor", "Google".parse().unwrap());
+    let gcp = match probe(&client, GCP_METADATA_URL, headers).await {
+        Ok(()) => return Ok(TEEType::GCP),
+        Err(e) => e,
+    };
+    let alicloud = match probe(&client, ALICLOUD_METADATA_URL, HeaderMap::new()).await {
+        Ok(()) => return Ok(TEEType::AliCloud),
+        Err(e) => e,
+    };
+    if Path::new(SEV_GUEST_DEVICE).exists() {
+        return Ok(TEEType::SevSnp);
+    }
+    Err(Error::UnkownTEETypeERROR {
+        message: format!("unkown tee type, gcp: {}, alicloud: {}", gcp, alicloud),
+    })
+}
+
+pub async fn get_current_tee_type() -> Result<TEEType, Error> {
+    detect_tee_type(Duration::from_millis(DETECT_TIMEOUT_MILLIS)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_detect_timeout() {
+        let start = Instant::now();
+        let _ = detect_tee_type(Duration::from_millis(200)).await;
+        // two probes, whether they are answered, refused or hang
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::VerificationError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha384};
+
+/// RTMR the node extends with the models it loads, the first one a TD guest can extend at runtime
+/// that isn't used by the boot chain.
+pub const MODEL_RTMR_INDEX: u64 = 3;
+pub const MODEL_EVENT_TYPE: u32 = 0x8000_0001;
+pub const RTMR_SIZE: usize = 48;
+
+pub type Rtmr = [u8; RTMR_SIZE];
+
+/// Model loaded by the node, serialized as the data of its measurement event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelMeasurement {
+    pub network: String,
+    pub model_id: u64,
+    pub name: String,
+    // sha256 of the model file
+    pub digest: String,
+}
+
+/// Event extended into an RTMR. The RTMR is extended with the sha384 of `event_data`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RtmrEvent {
+    pub rtmr_index: u64,
+    pub event_type: u32,
+    pub event_data: String,
+}
+
+impl RtmrEvent {
+    pub fn model(measurement: &ModelMeasurement) -> Self {
+        Self {
+            rtmr_index: MODEL_RTMR_INDEX,
+            event_type: MODEL_EVENT_TYPE,
+            event_data: serde_json::to_string(measurement).unwrap(),
+        }
+    }
+
+    pub fn extend_data(&self) -> Rtmr {
+        Sha384::digest(self.event_data.as_bytes()).into()
+    }
+
+    pub fn measurement(&self) -> Option<ModelMeasurement> {
+        match self.event_type {
+            MODEL_EVENT_TYPE => serde_json::from_str(&self.event_data).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Hex encoded TDX quote along with the events extended into its RTMRs since boot and the
+/// preimage of its report_data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TdxReport {
+    pub quote: String,
+    pub event_log: Vec<RtmrEvent>,
+    // hex encoded preimage of the report_data
+    #[serde(default)]
+    pub report_data: String,
+}
+
+pub fn extend(rtmr: &Rtmr, extend_data: &Rtmr) -> Rtmr {
+    let mut hasher = Sha384::new();
+    hasher.update(rtmr);
+    hasher.update(extend_data);
+    hasher.finalize().into()
+}
+
+/// Value the RTMR `index` holds after the events of the log, starting from zero.
+pub fn replay(event_log: &[RtmrEvent], index: u64) -> Result<Rtmr, VerificationError> {
+    if index > 3 {
+        return Err(VerificationError::EventLogError {
+            message: format!("invalid rtmr index {}", index),
+        });
+    }
+    Ok(event_log
+        .iter()
+        .filter(|e| e.rtmr_index == index)
+        .fold([0; RTMR_SIZE], |rtmr, e| extend(&rtmr, &e.extend_data())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(model_id: u64) -> RtmrEvent {
+        RtmrEvent::model(&ModelMeasurement {
+            network: "aizel".to_string(),
+            model_id,
+            name: format!("model-{}", model_id),
+            digest: "0".repeat(64),
+        })
+    }
+
+    #[test]
+    fn test_replay() {
+        let log = vec![event(1), event(2)];
+        let rtmr = extend(&extend(&[0; RTMR_SIZE], &log[0].extend_data()), &log[1].extend_data());
+        assert_eq!(replay(&log, MODEL_RTMR_INDEX).unwrap(), rtmr);
+        // the order of the loads is part of the measurement
+        assert_ne!(replay(&[event(2), event(1)], MODEL_RTMR_INDEX).unwrap(), rtmr);
+        assert_eq!(replay(&log, 2).unwrap(), [0; RTMR_SIZE]);
+        assert_eq!(log[1].measurement().unwrap().model_id, 2);
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// Report of the mock TEE: the report_data preimage signed by the node key it names. It only
+/// proves which node produced an output, not what code ran.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockReport {
+    // hex encoded preimage of the report_data
+    pub report_data: String,
+    // hex encoded compact ecdsa signature over the sha256 of the preimage
+    pub signature: String,
+}
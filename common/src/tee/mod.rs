@@ -1,11 +1,21 @@
+pub mod detect;
+pub mod event_log;
+pub mod mock;
 pub mod provider;
+pub mod report_data;
+pub mod sev_snp;
 pub mod verifier;
 use std::fmt;
-#[derive(Debug)]
+use std::str::FromStr;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TEEType {
     GCP,
     AliCloud,
     Unkown,
+    // reports signed with the node key, for running the node outside a TEE
+    Mock,
+    // AMD SEV-SNP guest on bare metal
+    SevSnp,
 }
 
 impl fmt::Display for TEEType {
@@ -14,6 +24,22 @@ impl fmt::Display for TEEType {
             TEEType::GCP => write!(f, "GCP"),
             TEEType::AliCloud => write!(f, "AliCloud"),
             TEEType::Unkown => write!(f, "Unkown"),
+            TEEType::Mock => write!(f, "Mock"),
+            TEEType::SevSnp => write!(f, "SevSnp"),
+        }
+    }
+}
+
+impl FromStr for TEEType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gcp" => Ok(TEEType::GCP),
+            "alicloud" | "alicloud-tdx" | "tdx" => Ok(TEEType::AliCloud),
+            "sevsnp" | "sev-snp" => Ok(TEEType::SevSnp),
+            "mock" => Ok(TEEType::Mock),
+            _ => Err(format!("unknown tee type {}", s)),
         }
     }
 }
@@ -0,0 +1,52 @@
+use sha2::{Digest, Sha256};
+
+pub const REPORT_DATA_VERSION: u8 = 1;
+
+/// What an attestation report vouches for. The TEE signs the sha256 of its `encode`d preimage:
+/// the first 32 bytes of the TDX report_data followed by the version, or the nonce of GCP tokens.
+/// Reports that aren't about a request, e.g. at registration, leave the request fields zero.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReportData {
+    // compressed public key of the node
+    pub node_pk: Vec<u8>,
+    pub network: String,
+    pub request_id: u64,
+    pub model_id: u64,
+    // hashes of the input and output ciphertexts, as submitted on chain
+    pub input_hash: [u8; 32],
+    pub output_hash: [u8; 32],
+}
+
+impl ReportData {
+    /// Preimage layout, integers big endian:
+    /// version u8 | node_pk len u16 | node_pk | network len u16 | network | request_id u64 |
+    /// model_id u64 | input_hash [32] | output_hash [32]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut preimage = vec![REPORT_DATA_VERSION];
+        for field in [self.node_pk.as_slice(), self.network.as_bytes()] {
+            preimage.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            preimage.extend_from_slice(field);
+        }
+        preimage.extend_from_slice(&self.request_id.to_be_bytes());
+        preimage.extend_from_slice(&self.model_id.to_be_bytes());
+        preimage.extend_from_slice(&self.input_hash);
+        preimage.extend_from_slice(&self.output_hash);
+        preimage
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.encode()).into()
+    }
+
+    /// report_data of TDX and SEV-SNP reports.
+    pub fn tdx_report_data(&self) -> [u8; 64] {
+        let mut report_data = [0; 64];
+        report_data[..32].copy_from_slice(&self.digest());
+        report_data[32] = REPORT_DATA_VERSION;
+        report_data
+    }
+
+    pub fn nonce(&self) -> String {
+        hex::encode(self.digest())
+    }
+}
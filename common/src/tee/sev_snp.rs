@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// Hex encoded SEV-SNP attestation report along with the preimage of its report_data and, when
+/// the host provides it, the DER of the VCEK that signed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SevSnpReport {
+    pub report: String,
+    pub report_data: String,
+    // fetched from the AMD key distribution service by the verifier if empty
+    #[serde(default)]
+    pub vcek: String,
+}
@@ -4,6 +4,8 @@ use async_trait::async_trait;
 
 #[async_trait]
 pub trait TEEVerifier: Send + Sync {
-    async fn verify(&self, report: String, skip_verify_image_digest: bool) -> Result<bool, Error>;
+    /// Verifies the report was issued for `nonce`, the hex sha256 of the expected `ReportData`.
+    /// Without a nonce, any report of a genuine TEE is accepted.
+    async fn verify(&self, report: String, nonce: Option<String>, skip_verify_image_digest: bool) -> Result<bool, Error>;
     fn get_type(&self) -> Result<TEEType, Error>;
 }
@@ -0,0 +1,117 @@
+use aizel_inference::node::config::network_config_path;
+use aizel_inference::verification::inference::{InferenceSource, Summary};
+use chrono::Local;
+use clap::Parser;
+use common::error::Error;
+use env_logger::Env;
+use std::io::Write;
+use std::path::PathBuf;
+use verifier::policy::Policy;
+
+/// Verifies the output and attestation report a node submitted for an inference request.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    network: String,
+
+    #[arg(long)]
+    request_id: u64,
+
+    /// YAML or JSON policy the report is checked against
+    #[arg(long)]
+    policy: PathBuf,
+
+    /// network configs as served by the config server, defaults to the one of the node
+    #[arg(long)]
+    network_config: Option<PathBuf>,
+
+    /// public data node the output and report are downloaded from, named after their hash
+    #[arg(long)]
+    data_node_url: Option<String>,
+
+    /// local copy of the output instead of downloading it
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+
+    /// local copy of the report instead of downloading it
+    #[arg(long)]
+    report_file: Option<PathBuf>,
+
+    /// nonce expected in GCP tokens, which don't carry the report_data preimage
+    #[arg(long)]
+    nonce: Option<String>,
+
+    /// print the summary as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn print(summary: &Summary, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(summary).unwrap());
+        return;
+    }
+    println!("request {} of network {}", summary.request_id, summary.network);
+    for check in summary.checks.iter() {
+        println!("[{:?}] {}: {}", check.status, check.name, check.detail);
+    }
+    println!("{}", if summary.passed { "PASS" } else { "FAIL" });
+}
+
+fn init_log() {
+    let _logger = env_logger::Builder::from_env(Env::default().default_filter_or("warn"))
+        .format(|buf, record| {
+            let level = { buf.default_level_style(record.level()) };
+            writeln!(
+                buf,
+                "{} {} [{}:{}] {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                format_args!("{:>5}", level),
+                record.module_path().unwrap_or("<unnamed>"),
+                record.line().unwrap_or(0),
+                &record.args()
+            )
+        })
+        .init();
+}
+
+async fn verify(args: &Args, summary: &mut Summary) -> Result<(), Error> {
+    let policy = Policy::from_file(&args.policy)?;
+    let source = InferenceSource {
+        network_config: args.network_config.clone().unwrap_or(network_config_path()),
+        data_node_url: args.data_node_url.clone(),
+        output_file: args.output_file.clone(),
+        report_file: args.report_file.clone(),
+    };
+    let Some(report) = source
+        .check(&args.network, args.request_id, args.nonce.clone(), summary)
+        .await?
+    else {
+        return Ok(());
+    };
+    let result = match policy.verifier(report.tee_type).await {
+        Ok(verifier) => verifier
+            .verify(report.report, report.nonce, policy.skip_verify_image_digest)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    summary.attestation(report.tee_type, result.map_err(|e| e.to_string()));
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    init_log();
+    let args = Args::parse();
+    let mut summary = Summary::new(&args.network, args.request_id);
+    if let Err(e) = verify(&args, &mut summary).await {
+        summary.fail("verification", e.to_string());
+    }
+    summary.finish();
+    print(&summary, args.json);
+    if !summary.passed {
+        std::process::exit(1);
+    }
+}
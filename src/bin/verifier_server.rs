@@ -0,0 +1,81 @@
+use aizel_inference::node::aizel::verifier_service_server::VerifierServiceServer;
+use aizel_inference::node::config::network_config_path;
+use aizel_inference::verification::inference::InferenceSource;
+use aizel_inference::verification::server::{load_policies, VerifierServer};
+use chrono::Local;
+use clap::Parser;
+use common::error::Error;
+use env_logger::Env;
+use log::info;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tonic::transport::Server;
+
+/// Verifies attestation reports of inference nodes for third parties over gRPC and JSON/HTTP.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// directory of the YAML or JSON policies, named after their file stem
+    #[arg(long)]
+    policy_dir: PathBuf,
+
+    #[arg(long, default_value_t = 7070)]
+    grpc_port: u16,
+
+    #[arg(long, default_value_t = 7080)]
+    http_port: u16,
+
+    /// network configs as served by the config server, defaults to the one of the node
+    #[arg(long)]
+    network_config: Option<PathBuf>,
+
+    /// public data node the outputs and reports of requests are downloaded from
+    #[arg(long)]
+    data_node_url: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let _logger = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .format(|buf, record| {
+            let level = { buf.default_level_style(record.level()) };
+            writeln!(
+                buf,
+                "{} {} [{}:{}] {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                format_args!("{:>5}", level),
+                record.module_path().unwrap_or("<unnamed>"),
+                record.line().unwrap_or(0),
+                &record.args()
+            )
+        })
+        .init();
+
+    let policies = load_policies(&args.policy_dir)?;
+    let source = InferenceSource {
+        network_config: args.network_config.unwrap_or(network_config_path()),
+        data_node_url: args.data_node_url,
+        ..Default::default()
+    };
+    let server = Arc::new(VerifierServer::new(policies, source));
+
+    let grpc_addr = SocketAddr::from(([0, 0, 0, 0], args.grpc_port));
+    let http_addr = SocketAddr::from(([0, 0, 0, 0], args.http_port));
+    info!("serve grpc on {} and http on {}", grpc_addr, http_addr);
+    let grpc = Server::builder()
+        .add_service(VerifierServiceServer::new(server.clone()))
+        .serve(grpc_addr);
+    tokio::try_join!(
+        async {
+            grpc.await.map_err(|e| Error::ServerError {
+                message: format!("failed to listen: {}", e),
+            })
+        },
+        server.serve_http(http_addr),
+    )?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::node::config::{AIZEL_CONFIG, NETWORK_CONFIGS};
+use crate::node::config::{NetworkConfig, AIZEL_CONFIG, NETWORK_CONFIGS, NONCE_SYNC_RETRIES};
 use common::error::Error;
 use ethers::core::{
     abi::{self, Token},
@@ -7,27 +7,116 @@ use ethers::core::{
 use ethers::{
     contract::abigen,
     middleware::SignerMiddleware,
-    providers::{Http, Provider},
+    providers::{Http, Middleware, Provider},
     signers::{LocalWallet, Signer},
-    types::{Address, Bytes, U256, H160},
+    types::{transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, H160, H256, U256},
 };
 use lazy_static::lazy_static;
-use log::{error, info};
+use log::{error, info, warn};
 use std::{collections::HashMap, str::FromStr};
 use std::sync::Arc;
-use super::nonce_manager::LocalNonceManager;
-#[derive(Debug)]
+use super::nonce_manager::{is_nonce_error, LocalNonceManager, NONCE_MANAGERS};
+use crate::node::backend::{LLAMA_BACKEND, ML_BACKEND};
+use super::tx_manager::{self, TxOutcome, TxPolicy, TxStatus};
+#[derive(Debug, Clone)]
 pub struct ModelInfo {
     pub name: String,
     pub cid: String,
     pub id: u64,
-    pub network: String
+    pub network: String,
+    // size of the model file in bytes, 0 if unknown and the model can't be verified
+    pub size: u64,
 }
 
+impl ModelInfo {
+    /// Backend the model file recorded on chain is meant for, if its format tells.
+    pub fn backend_hint(&self) -> Option<&'static str> {
+        if self.name.contains(".gguf") {
+            Some(LLAMA_BACKEND)
+        } else if self.name.contains(".tar.gz") {
+            Some(ML_BACKEND)
+        } else {
+            None
+        }
+    }
+}
+
+/// Id read from a contract, rejected if it doesn't fit the u64 ids the node works with.
+pub fn to_u64(value: U256, name: &str) -> Result<u64, Error> {
+    value.try_into().map_err(|_| Error::InvalidArgumentError {
+        argument: name.to_string(),
+        message: format!("{} doesn't fit in 64 bits", value),
+    })
+}
+
+// storage slot holding the implementation of an EIP-1967 proxy
+const EIP1967_IMPLEMENTATION_SLOT: &str = "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+
+/// Whether `code` pushes `value`, the way solidity dispatches on a selector or emits an event topic.
+/// Leading zero bytes are left out of the push like the compiler does.
+fn pushes(code: &[u8], value: &[u8]) -> bool {
+    let value = &value[value.iter().take_while(|b| **b == 0).count()..];
+    let mut push = vec![0x5f + value.len() as u8];
+    push.extend_from_slice(value);
+    code.windows(push.len()).any(|w| w == push.as_slice())
+}
+
+/// Signatures of the `functions` and `events` of `abi` the bytecode doesn't implement. Some bindings
+/// aren't taken from a deployed ABI, a mismatch would otherwise only show as reverted calls or
+/// events that never match.
+pub fn unimplemented_bindings(code: &[u8], abi: &abi::Abi, functions: &[&str], events: &[&str]) -> Vec<String> {
+    let signature = |name: &str, inputs: Vec<String>| format!("{}({})", name, inputs.join(","));
+    let mut missing = vec![];
+    for function in functions.iter().filter_map(|f| abi.function(f).ok()) {
+        if !pushes(code, &function.short_signature()) {
+            missing.push(signature(&function.name, function.inputs.iter().map(|p| p.kind.to_string()).collect()));
+        }
+    }
+    for event in events.iter().filter_map(|e| abi.event(e).ok()) {
+        if !pushes(code, event.signature().as_bytes()) {
+            missing.push(signature(&event.name, event.inputs.iter().map(|p| p.kind.to_string()).collect()));
+        }
+    }
+    missing
+}
+
+/// Like `unimplemented_bindings` for the contract deployed at `address`, following EIP-1967 proxies
+/// to their implementation.
+pub async fn missing_bindings<M: Middleware>(client: &M, address: Address, abi: &abi::Abi, functions: &[&str], events: &[&str]) -> Result<Vec<String>, Error> {
+    let contract_error = |e: M::Error| Error::ContractError {
+        message: format!("failed to read the code of contract {:?}: {}", address, e),
+    };
+    let mut code = client.get_code(address, None).await.map_err(contract_error)?.to_vec();
+    let slot = H256::from_str(EIP1967_IMPLEMENTATION_SLOT).unwrap();
+    let implementation = client.get_storage_at(address, slot, None).await.map_err(contract_error)?;
+    if !implementation.is_zero() {
+        let implementation = Address::from_slice(&implementation.as_bytes()[12..]);
+        code.extend_from_slice(&client.get_code(implementation, None).await.map_err(contract_error)?);
+    }
+    Ok(unimplemented_bindings(&code, abi, functions, events))
+}
+
+/// A request as recorded by the inference contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnChainRequest {
+    pub node_id: u64,
+    pub model_id: u64,
+    pub user_pk: String,
+    pub input: String,
+    pub req_type: u8,
+    // whether a result was submitted for it
+    pub settled: bool,
+}
+
+// getInferenceResult, getRequest, InferenceRequested and getNodeId are not taken from the ABI of a
+// deployed contract, what relies on them checks the deployed bytecode with `missing_bindings` first
 abigen!(
     InferenceContract,
     r#"[
         function submitInference(uint256 requestId,bytes32 output,bytes32 report) external
+        function getInferenceResult(uint256 requestId) external view returns (bytes32 output, bytes32 report)
+        function getRequest(uint256 requestId) external view returns (uint256 nodeId, uint256 modelId, string userPk, string input, uint8 reqType, bool settled)
+        event InferenceRequested(uint256 indexed requestId, uint256 indexed nodeId, uint256 modelId, string userPk, string input, uint8 reqType)
     ]"#,
 );
 
@@ -37,6 +126,7 @@ abigen!(
         function registerNode(string memory name,string memory bio,string memory url,string memory pubkey,uint256 dataNodeId,uint32 teeType) external payable returns (uint256 id)
         function getMinStake() external view returns (uint256)
         function pubkeyExists(string calldata pubkey) public view returns (bool)
+        function getNodeId(string calldata pubkey) public view returns (uint256)
     ]"#,
 );
 
@@ -196,14 +286,30 @@ lazy_static! {
 }
 
 impl Contract {
-    pub async fn get_nonce(network: &str) -> Result<U256, Error> {
-        let nonce_manager = NONCE_MANAGERS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
-        Ok(nonce_manager.next().await)
+    fn nonce_manager(network: &str) -> Result<&'static LocalNonceManager, Error> {
+        NONCE_MANAGERS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })
+    }
+
+    /// Transaction count of the wallet including the transactions in the mempool.
+    async fn pending_nonce<M: Middleware>(client: &M) -> Result<U256, Error> {
+        let from = client.default_sender().ok_or(Error::ContractError {
+            message: "no wallet to send transactions".to_string(),
+        })?;
+        client
+            .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|e| Error::ContractError {
+                message: e.to_string(),
+            })
     }
 
-    pub async fn unuse_nonce(network: &str, unused: U256) -> Result<(), Error> {
-        let nonce_manager = NONCE_MANAGERS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
-        Ok(nonce_manager.save_unused(unused).await)
+    /// Reconciles the nonces of the node wallet on `network` with the chain.
+    pub async fn sync_nonce(network: &str) -> Result<(), Error> {
+        let contract = INFERENCE_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
+        let pending = Self::pending_nonce(contract.client_ref()).await?;
+        info!("network {} pending nonce {}", network, pending);
+        Self::nonce_manager(network)?.resync(pending).await;
+        Ok(())
     }
 
     pub async fn register(
@@ -225,22 +331,55 @@ impl Contract {
             data_node_id.into(),
             tee_type.into(),
         );
-        let nonce = Self::get_nonce(network).await?;
-        info!("register nonce {}", nonce);
-        let tx = tx.nonce::<U256>(nonce.clone());
+        info!("register on network {}", network);
         let tx = tx.value::<U256>(stake_amount.into());
-        match tx.send().await {
-            Ok(_) => {}
-            Err(e) => {
-                Self::unuse_nonce(network, nonce).await?;
-                return Err(Error::RegistrationError {
-                    message: e.to_string(),
-                });
-            }
+        let outcome = Self::send_tx(contract.client_ref(), tx.tx, network).await.map_err(|e| Error::RegistrationError {
+            message: e.to_string(),
+        })?;
+        if outcome.status != TxStatus::Confirmed {
+            return Err(Error::RegistrationError {
+                message: format!("registration tx {:?} {}", outcome.tx_hash, outcome.status),
+            });
         }
         Ok(())
     }
 
+    /// Sends `tx` with the next nonce of the wallet and waits until it is final. A nonce that never
+    /// made it on chain is given back, a nonce error resyncs the nonces and sends again.
+    async fn send_tx<M: Middleware>(client: &M, tx: TypedTransaction, network: &str) -> Result<TxOutcome, Error> {
+        let nonce_manager = Self::nonce_manager(network)?;
+        let mut retries = 0;
+        loop {
+            let nonce = nonce_manager.next().await;
+            info!("network {} send tx with nonce {}", network, nonce);
+            match tx_manager::send(client, tx.clone(), nonce, &TxPolicy::from_config()).await {
+                Ok(outcome) => {
+                    match outcome.status {
+                        TxStatus::Dropped => nonce_manager.reclaim(nonce, Self::pending_nonce(client).await?).await,
+                        TxStatus::Replaced => {
+                            // someone else used the nonce, the following ones may be taken as well
+                            nonce_manager.done(nonce).await;
+                            nonce_manager.resync(Self::pending_nonce(client).await?).await;
+                        }
+                        // the chain counts a pending nonce as taken, a resync frees it if it is dropped later
+                        TxStatus::Confirmed | TxStatus::Reverted | TxStatus::Pending => nonce_manager.done(nonce).await,
+                    }
+                    return Ok(outcome);
+                }
+                Err(e) => {
+                    let pending = Self::pending_nonce(client).await?;
+                    nonce_manager.reclaim(nonce, pending).await;
+                    if !is_nonce_error(&e.to_string()) || retries >= NONCE_SYNC_RETRIES {
+                        return Err(e);
+                    }
+                    warn!("nonce {} out of sync on network {}: {}", nonce, network, e);
+                    nonce_manager.resync(pending).await;
+                    retries += 1;
+                }
+            }
+        }
+    }
+
     pub async fn query_data_node_url(data_node_id: u64, network: &str) -> Result<String, Error> {
         let contract = DATA_REGISTRY_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
         let data_node_url: String = contract
@@ -259,23 +398,16 @@ impl Contract {
         output_hash: [u8; 32],
         report_hash: [u8; 32],
         network: &str
-    ) -> Result<(), Error> {
+    ) -> Result<TxOutcome, Error> {
         let contract = INFERENCE_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
         let tx = contract.submit_inference(request_id.into(), output_hash, report_hash);
-        let nonce: U256 = Self::get_nonce(network).await?;
-        info!("submit inference: network {} request id {}, nonce {}", network, request_id, nonce);
-        let tx = tx.nonce::<U256>(nonce.clone());
-        match tx.send().await {
-            Ok(_) => {},
-            Err(e) => {
-                error!("failed to submit inference result: {}", e.to_string());
-                Self::unuse_nonce(network, nonce).await?;
-                return Err(Error::InferenceError {
-                    message: format!("failed to submit inference reuslt {}", e.to_string()),
-                });
+        info!("submit inference: network {} request id {}", network, request_id);
+        Self::send_tx(contract.client_ref(), tx.tx, network).await.map_err(|e| {
+            error!("failed to submit inference result: {}", e.to_string());
+            Error::InferenceError {
+                message: format!("failed to submit inference reuslt {}", e.to_string()),
             }
-        }
-        Ok(())
+        })
     }
 
     pub async fn query_public_key_exist(public_key: String, network: &str) -> Result<bool, Error> {
@@ -290,6 +422,29 @@ impl Contract {
         return Ok(exist);
     }
 
+    /// Signatures of `getRequest` and `getNodeId` the contracts of `network` don't implement.
+    pub async fn missing_request_bindings(network: &str) -> Result<Vec<String>, Error> {
+        let not_found = || Error::NetworkConfigNotFoundError { network: network.to_string() };
+        let contract = INFERENCE_CONTRACTS.get(network).ok_or_else(not_found)?;
+        let mut missing = missing_bindings(contract.client_ref(), contract.address(), &INFERENCECONTRACT_ABI, &["getRequest"], &[]).await?;
+        let registry = INFERENCE_REGISTRY_CONTRACTS.get(network).ok_or_else(not_found)?;
+        missing.extend(missing_bindings(registry.client_ref(), registry.address(), &INFERENCEREGISTRYCONTRACT_ABI, &["getNodeId"], &[]).await?);
+        Ok(missing)
+    }
+
+    /// Id the node registered with `public_key` got, 0 if it isn't registered yet.
+    pub async fn query_node_id(public_key: String, network: &str) -> Result<u64, Error> {
+        let contract = INFERENCE_REGISTRY_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
+        let node_id: U256 = contract
+            .get_node_id(public_key)
+            .call()
+            .await
+            .map_err(|e| Error::ContractError {
+                message: e.to_string(),
+            })?;
+        to_u64(node_id, "node_id")
+    }
+
     pub async fn query_model(model_id: u64, network: &str) -> Result<ModelInfo, Error> {
         let contract = MODEL_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
         let model: ModelDetails = contract
@@ -303,10 +458,67 @@ impl Contract {
             name: model.model_name,
             cid: model.cid,
             id: model_id,
-            network: network.to_string()
+            network: network.to_string(),
+            size: model.size.try_into().unwrap_or(0),
         });
     }
 
+    /// Request `request_id` as recorded on chain, None if it was never created.
+    pub async fn query_request(request_id: u64, network: &str) -> Result<Option<OnChainRequest>, Error> {
+        let contract = INFERENCE_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
+        let (node_id, model_id, user_pk, input, req_type, settled) = contract
+            .get_request(request_id.into())
+            .call()
+            .await
+            .map_err(|e| Error::ContractError {
+                message: e.to_string(),
+            })?;
+        // unknown ids read as a zeroed request, every created one is assigned to a node
+        if node_id.is_zero() {
+            return Ok(None);
+        }
+        Ok(Some(OnChainRequest {
+            node_id: to_u64(node_id, "node_id")?,
+            model_id: to_u64(model_id, "model_id")?,
+            user_pk,
+            input,
+            req_type,
+            settled,
+        }))
+    }
+
+    /// Output and report hashes submitted for a request, read without a wallet so tools that don't
+    /// run a node can query them.
+    pub async fn query_inference_result(request_id: u64, network_config: &NetworkConfig) -> Result<([u8; 32], [u8; 32]), Error> {
+        let provider = Provider::<Http>::try_from(network_config.rpc_url.clone()).map_err(|e| Error::InvalidArgumentError {
+            argument: network_config.rpc_url.clone(),
+            message: e.to_string(),
+        })?;
+        let address = network_config
+            .contracts
+            .iter()
+            .find(|a| a.name == "INFERENCE")
+            .ok_or(Error::NetworkConfigNotFoundError { network: network_config.network.clone() })?
+            .address;
+        let contract = InferenceContract::new(address, Arc::new(provider));
+        match contract.get_inference_result(request_id.into()).call().await {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                // tell a contract without the function from a failed call
+                let missing = missing_bindings(contract.client_ref(), address, &INFERENCECONTRACT_ABI, &["getInferenceResult"], &[]).await;
+                let message = match missing {
+                    Ok(missing) if !missing.is_empty() => format!(
+                        "inference contract of network {} doesn't implement {}",
+                        network_config.network,
+                        missing.join(", ")
+                    ),
+                    _ => e.to_string(),
+                };
+                Err(Error::ContractError { message })
+            }
+        }
+    }
+
     pub async fn query_data_node_default_model(data_node_id: u64, network: &str) -> Result<ModelInfo, Error> {
         let contract = MODEL_CONTRACTS.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
         let models: Vec<ModelDetails> = contract
@@ -323,7 +535,8 @@ impl Contract {
                 name: models[0].model_name.clone(),
                 cid: models[0].cid.clone(),
                 id: models[0].model_id.try_into().unwrap(),
-                network: network.to_string()
+                network: network.to_string(),
+                size: models[0].size.try_into().unwrap_or(0),
             });
         }
     }
@@ -375,28 +588,19 @@ impl Contract {
             amount,
             Bytes::from_iter(signature),
         );
-        let nonce = Self::get_nonce(network).await?;
-        let tx = tx.nonce::<U256>(nonce.clone());
-        match tx.send().await {
-            Ok(_) => {},
-            Err(e) => {
-                Self::unuse_nonce(network, nonce).await?;
-                return Err(Error::InferenceError {
-                    message: format!("failed to transfer token {}", e.to_string()),
-                });
-            }
+        let outcome = Self::send_tx(contract.client_ref(), tx.tx, network).await.map_err(|e| Error::InferenceError {
+            message: format!("failed to transfer token {}", e.to_string()),
+        })?;
+        if outcome.status != TxStatus::Confirmed {
+            return Err(Error::InferenceError {
+                message: format!("transfer tx {:?} {}", outcome.tx_hash, outcome.status),
+            });
         }
         Ok(())
     }
 }
 
 lazy_static! {
-    pub static ref NONCE_MANAGERS: HashMap<String, LocalNonceManager> = {
-        NETWORK_CONFIGS.get().unwrap().iter().map(|c| {
-            (c.network.clone(), LocalNonceManager::new())
-        }).collect()
-    };
-
     pub static ref INFERENCE_CONTRACTS: HashMap<String, InferenceContract<SignerMiddleware<Provider<Http>, LocalWallet>>> = {
         NETWORK_CONFIGS.get().unwrap().iter().map(|c| {
             let provider = Provider::<Http>::try_from(c.rpc_url.clone()).unwrap();
@@ -513,3 +717,46 @@ async fn query_model() {
     let mut archive = Archive::new(tar);
     archive.unpack(ml_models_dir("aizel")).unwrap();
 }
+
+#[tokio::test]
+async fn test_missing_bindings() {
+    let abi: &abi::Abi = &INFERENCECONTRACT_ABI;
+    let selector = abi.function("getInferenceResult").unwrap().short_signature();
+    let topic = abi.event("InferenceRequested").unwrap().signature();
+    // PUSH4 selector EQ, PUSH32 topic LOG3
+    let mut code = vec![0x63];
+    code.extend_from_slice(&selector);
+    code.extend_from_slice(&[0x14, 0x7f]);
+    code.extend_from_slice(topic.as_bytes());
+    code.push(0xa3);
+    assert!(unimplemented_bindings(&code, abi, &["getInferenceResult"], &["InferenceRequested"]).is_empty());
+    assert_eq!(
+        unimplemented_bindings(&code, abi, &["getRequest"], &[]),
+        vec!["getRequest(uint256)".to_string()]
+    );
+    assert_eq!(
+        unimplemented_bindings(&code[5..], abi, &["getInferenceResult"], &[]),
+        vec!["getInferenceResult(uint256)".to_string()]
+    );
+
+    // the proxy only delegates, the implementation dispatches
+    let (provider, mock) = Provider::mocked();
+    let implementation = H256::from_low_u64_be(0xbeef);
+    mock.push::<Bytes, _>(Bytes::from(code)).unwrap();
+    mock.push::<H256, _>(implementation).unwrap();
+    mock.push::<Bytes, _>(Bytes::from(vec![0x36, 0x3d, 0xf4])).unwrap();
+    let missing = missing_bindings(&provider, Address::zero(), abi, &["getInferenceResult"], &["InferenceRequested"])
+        .await
+        .unwrap();
+    assert!(missing.is_empty());
+}
+
+#[test]
+fn test_decode_inference_result() {
+    use ethers::core::abi::AbiDecode;
+    // eth_call return data of getInferenceResult(uint256) returns (bytes32 output, bytes32 report)
+    let data = hex::decode(format!("{}{}", "11".repeat(32), "22".repeat(32))).unwrap();
+    let result = GetInferenceResultReturn::decode(data).unwrap();
+    assert_eq!(result.output, [0x11; 32]);
+    assert_eq!(result.report, [0x22; 32]);
+}
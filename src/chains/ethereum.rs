@@ -3,9 +3,17 @@ use hex;
 use secp256k1::PublicKey;
 use sha3::{Digest, Keccak256};
 pub fn pubkey_to_address(pubkey: &str) -> Result<String, Error> {
-    let compressed_pub_key_bytes = hex::decode(pubkey).unwrap();
-    let public_key = PublicKey::from_slice(&compressed_pub_key_bytes).unwrap();
-    ethereum_address(&public_key)
+    ethereum_address(&parse_pubkey(pubkey)?)
+}
+
+/// Parses the hex encoded secp256k1 public key of a user.
+pub fn parse_pubkey(pubkey: &str) -> Result<PublicKey, Error> {
+    let invalid = |message: String| Error::InvalidArgumentError {
+        argument: "user_pk".to_string(),
+        message,
+    };
+    let pub_key_bytes = hex::decode(pubkey).map_err(|e| invalid(e.to_string()))?;
+    PublicKey::from_slice(&pub_key_bytes).map_err(|e| invalid(e.to_string()))
 }
 
 fn ethereum_address(pub_key: &PublicKey) -> Result<String, Error> {
@@ -37,5 +45,7 @@ mod test {
             address,
             "0xc68884d8be3d37e2fd61837cb65bc72aa5a4ebcf".to_string()
         );
+        assert!(pubkey_to_address("not hex").is_err());
+        assert!(pubkey_to_address("0102").is_err());
     }
 }
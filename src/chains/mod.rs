@@ -1,3 +1,5 @@
 pub mod contract;
 pub mod ethereum;
 pub mod nonce_manager;
+pub mod tx_manager;
+pub mod watcher;
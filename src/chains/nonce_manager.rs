@@ -1,58 +1,211 @@
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use ethers::types::U256;
-use queues::{IsQueue, Queue};
+use common::error::Error;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use lazy_static::lazy_static;
-use crate::node::config::NETWORK_CONFIGS;
+use crate::node::config::{nonce_path, NETWORK_CONFIGS};
 lazy_static! {
     pub static ref NONCE_MANAGERS: HashMap<String, LocalNonceManager> = {
         NETWORK_CONFIGS.get().unwrap().iter().map(|c| {
-            (c.network.clone(), LocalNonceManager::new())
+            (c.network.clone(), LocalNonceManager::open(nonce_path(&c.network)))
         }).collect()
     };
 }
 
+/// Whether a send failed because its nonce was already taken or is out of the accepted range,
+/// the local nonces are out of sync with the chain then.
+pub fn is_nonce_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["nonce too low", "nonce too high", "invalid nonce", "nonce has already been used", "already known", "replacement transaction underpriced"]
+        .iter()
+        .any(|e| message.contains(e))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct NonceState {
+    // next nonce never handed out
+    next: u64,
+    // nonces below `next` that were handed out but never made it on chain
+    unused: BTreeSet<u64>,
+}
+
+struct Nonces {
+    state: NonceState,
+    // nonces of the transactions still being followed, not persisted
+    in_flight: HashSet<u64>,
+}
+
+/// Hands out the nonces of the node wallet on a network, persisted so they survive restarts.
+/// The local view is reconciled with the pending nonce of the chain whenever it may be off: on
+/// startup, when a send fails with a nonce error and before a nonce is given back.
 pub struct LocalNonceManager {
-    nonces: AtomicU64,
-    unused: Mutex<Queue<u64>>,
+    path: Option<PathBuf>,
+    nonces: Mutex<Nonces>,
 }
 
 impl LocalNonceManager {
     pub fn new() -> Self {
+        Self::with_state(None, NonceState::default())
+    }
+
+    /// Loads the nonces persisted at `path`, starting over if they can't be read.
+    pub fn open(path: PathBuf) -> Self {
+        let state = match Self::load(&path) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("failed to load nonces, resync from the chain: {}", e);
+                NonceState::default()
+            }
+        };
+        Self::with_state(Some(path), state)
+    }
+
+    fn with_state(path: Option<PathBuf>, state: NonceState) -> Self {
         Self {
-            nonces: AtomicU64::new(0),
-            unused: Mutex::new(Queue::new())
+            path,
+            nonces: Mutex::new(Nonces {
+                state,
+                in_flight: HashSet::new(),
+            }),
+        }
+    }
+
+    fn load(path: &Path) -> Result<NonceState, Error> {
+        if !path.exists() {
+            return Ok(NonceState::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        serde_json::from_str(&content).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse nonces {}", e),
+        })
+    }
+
+    fn persist(&self, state: &NonceState) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let tmp = path.with_extension("tmp");
+        let result = path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::write(&tmp, serde_json::to_string(state).unwrap()))
+            .and_then(|_| std::fs::rename(&tmp, path));
+        if let Err(e) = result {
+            warn!("failed to persist nonces to {:?}: {}", path, e);
         }
     }
 
-    /// Returns the next nonce to be used
+    /// Returns the next nonce to be used, the lowest unused one first.
     pub async fn next(&self) -> U256 {
-        let mut queue = self.unused.lock().await;
-        if queue.size() == 0 {
-            let nonce = self.nonces.fetch_add(1, Ordering::SeqCst);
-            nonce.into()
-        } else {
-            let unused = queue.remove().unwrap();
-            unused.into()
+        let mut nonces = self.nonces.lock().await;
+        let nonce = match nonces.state.unused.pop_first() {
+            Some(unused) => unused,
+            None => {
+                nonces.state.next += 1;
+                nonces.state.next - 1
+            }
+        };
+        nonces.in_flight.insert(nonce);
+        self.persist(&nonces.state);
+        nonce.into()
+    }
+
+    /// The transaction with `nonce` is no longer followed, whether it was mined, replaced or is left
+    /// pending.
+    pub async fn done(&self, nonce: U256) {
+        self.nonces.lock().await.in_flight.remove(&nonce.as_u64());
+    }
+
+    /// Gives `nonce` back unless the chain already counts it, a send may fail after broadcasting.
+    /// `pending` is the pending transaction count of the wallet.
+    pub async fn reclaim(&self, nonce: U256, pending: U256) {
+        let mut nonces = self.nonces.lock().await;
+        let nonce = nonce.as_u64();
+        nonces.in_flight.remove(&nonce);
+        if nonce >= pending.as_u64() && nonce < nonces.state.next {
+            nonces.state.unused.insert(nonce);
+            self.persist(&nonces.state);
         }
     }
 
-    pub fn initialize_nonce(&self, initial_nonce: U256) {
-        self.nonces.store(initial_nonce.as_u64(), Ordering::SeqCst);
+    /// Reconciles the local nonces with `pending`, the pending transaction count of the wallet.
+    /// Every nonce below it is taken. The ones from it that aren't in flight were never mined and
+    /// are reused, transactions sent by another tool move the counter ahead.
+    pub async fn resync(&self, pending: U256) {
+        let mut nonces = self.nonces.lock().await;
+        let pending = pending.as_u64();
+        let Nonces { state, in_flight } = &mut *nonces;
+        state.unused.retain(|n| *n >= pending);
+        if pending >= state.next {
+            state.next = pending;
+        } else {
+            let gaps: Vec<u64> = (pending..state.next).filter(|n| !in_flight.contains(n)).collect();
+            if !gaps.is_empty() {
+                info!("reclaim unmined nonces {:?}", gaps);
+            }
+            state.unused.extend(gaps);
+        }
+        self.persist(state);
     }
+}
 
-    pub async fn save_unused(&self, nonce: U256) {
-        let mut queue = self.unused.lock().await;
-        let _ = queue.add(nonce.as_u64());
+impl Default for LocalNonceManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[tokio::test]
-async fn test_nonce_manager() {
-    let nonce_manager = LocalNonceManager::new();
-    nonce_manager.initialize_nonce(10.into());
-    assert_eq!(nonce_manager.next().await, 10.into());
-    nonce_manager.save_unused(10.into()).await;
-    assert_eq!(nonce_manager.next().await, 10.into());
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_nonce_manager() {
+        let nonce_manager = LocalNonceManager::new();
+        nonce_manager.resync(10.into()).await;
+        assert_eq!(nonce_manager.next().await, 10.into());
+        // broadcast before failing, the chain counts it
+        nonce_manager.reclaim(10.into(), 11.into()).await;
+        assert_eq!(nonce_manager.next().await, 11.into());
+        nonce_manager.reclaim(11.into(), 11.into()).await;
+        assert_eq!(nonce_manager.next().await, 11.into());
+
+        // another tool sent 3 transactions
+        nonce_manager.resync(15.into()).await;
+        assert_eq!(nonce_manager.next().await, 15.into());
+
+        // 16 and 17 never reached the chain while 18 is still followed
+        assert_eq!(nonce_manager.next().await, 16.into());
+        assert_eq!(nonce_manager.next().await, 17.into());
+        assert_eq!(nonce_manager.next().await, 18.into());
+        nonce_manager.done(16.into()).await;
+        nonce_manager.done(17.into()).await;
+        nonce_manager.resync(16.into()).await;
+        assert_eq!(nonce_manager.next().await, 16.into());
+        assert_eq!(nonce_manager.next().await, 17.into());
+        assert_eq!(nonce_manager.next().await, 19.into());
+    }
+
+    #[tokio::test]
+    async fn test_persist_nonces() {
+        let path = std::env::temp_dir().join(format!("aizel_nonces_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let nonce_manager = LocalNonceManager::open(path.clone());
+        nonce_manager.resync(5.into()).await;
+        assert_eq!(nonce_manager.next().await, 5.into());
+        assert_eq!(nonce_manager.next().await, 6.into());
+        nonce_manager.reclaim(5.into(), 5.into()).await;
+
+        let reopened = LocalNonceManager::open(path.clone());
+        assert_eq!(reopened.next().await, 5.into());
+        assert_eq!(reopened.next().await, 7.into());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+use crate::node::config::{tx_confirmations, tx_gas_bump_percent, tx_max_rebroadcasts, tx_stuck_timeout_secs, TX_POLL_INTERVAL_MILLIS};
+use common::error::Error;
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip2718::TypedTransaction, Address, BlockNumber, TransactionReceipt, H256, U256},
+};
+use log::{info, warn};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Final state of a transaction sent by the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    // mined successfully and buried under the configured number of blocks
+    Confirmed,
+    Reverted,
+    // another transaction with the same nonce was mined
+    Replaced,
+    // never mined and unknown to the node, its nonce is still free
+    Dropped,
+    // not mined after every re-broadcast but may still be, its nonce is taken
+    Pending,
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            TxStatus::Confirmed => "confirmed",
+            TxStatus::Reverted => "reverted",
+            TxStatus::Replaced => "replaced",
+            TxStatus::Dropped => "dropped",
+            TxStatus::Pending => "pending",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TxOutcome {
+    // hash of the last broadcast, or of the one that was mined
+    pub tx_hash: H256,
+    pub status: TxStatus,
+    pub block: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxPolicy {
+    pub confirmations: u64,
+    // time a broadcast may stay unmined before it is re-broadcast with more gas
+    pub stuck_timeout: Duration,
+    pub max_rebroadcasts: u32,
+    pub gas_bump_percent: u64,
+    pub poll_interval: Duration,
+}
+
+impl TxPolicy {
+    pub fn from_config() -> Self {
+        Self {
+            confirmations: tx_confirmations(),
+            stuck_timeout: Duration::from_secs(tx_stuck_timeout_secs()),
+            max_rebroadcasts: tx_max_rebroadcasts(),
+            gas_bump_percent: tx_gas_bump_percent(),
+            poll_interval: Duration::from_millis(TX_POLL_INTERVAL_MILLIS),
+        }
+    }
+}
+
+fn bumped(price: U256, percent: u64) -> U256 {
+    // nodes only accept a replacement paying strictly more
+    price * (100 + percent) / 100 + 1
+}
+
+/// Raises the gas price of `tx` by `percent` so it replaces the broadcast with the same nonce.
+pub fn bump_gas(tx: &mut TypedTransaction, percent: u64) {
+    match tx {
+        TypedTransaction::Eip1559(inner) => {
+            inner.max_fee_per_gas = inner.max_fee_per_gas.map(|p| bumped(p, percent));
+            inner.max_priority_fee_per_gas = inner.max_priority_fee_per_gas.map(|p| bumped(p, percent));
+        }
+        _ => {
+            if let Some(price) = tx.gas_price() {
+                tx.set_gas_price(bumped(price, percent));
+            }
+        }
+    }
+}
+
+fn client_error(e: impl fmt::Display) -> Error {
+    Error::ContractError { message: e.to_string() }
+}
+
+enum Wait {
+    Done(TxOutcome),
+    // nothing mined in time or the node forgot the last broadcast
+    Stuck,
+}
+
+/// Sends `tx` with `nonce` and follows it until it is final. A broadcast that stays unmined or is
+/// dropped from the mempool is sent again with bumped gas, any of the broadcasts may end up mined.
+/// Errors only if the first broadcast fails, the nonce is unused then.
+pub async fn send<M: Middleware>(client: &M, mut tx: TypedTransaction, nonce: U256, policy: &TxPolicy) -> Result<TxOutcome, Error> {
+    tx.set_nonce(nonce);
+    client.fill_transaction(&mut tx, None).await.map_err(client_error)?;
+    let from = tx.from().copied().unwrap_or_else(|| client.default_sender().unwrap_or_default());
+    let mut hashes: Vec<H256> = vec![];
+    let mut broadcasts = 0;
+    loop {
+        match client.send_transaction(tx.clone(), None).await {
+            Ok(pending) => hashes.push(pending.tx_hash()),
+            Err(e) if hashes.is_empty() => return Err(client_error(e)),
+            // the previous broadcast may have been mined meanwhile, the receipts tell
+            Err(e) => warn!("failed to re-broadcast tx with nonce {}: {}", nonce, e),
+        }
+        broadcasts += 1;
+        if let Wait::Done(outcome) = wait(client, &hashes, from, nonce, policy).await? {
+            info!("tx {:?} with nonce {} {}", outcome.tx_hash, nonce, outcome.status);
+            return Ok(outcome);
+        }
+        if broadcasts > policy.max_rebroadcasts {
+            let status = unmined_status(client, &hashes, from, nonce).await?;
+            warn!("tx with nonce {} not mined after {} broadcasts, {}", nonce, broadcasts, status);
+            return Ok(TxOutcome {
+                tx_hash: *hashes.last().unwrap(),
+                status,
+                block: None,
+            });
+        }
+        bump_gas(&mut tx, policy.gas_bump_percent);
+        info!("re-broadcast tx with nonce {} with {}% more gas", nonce, policy.gas_bump_percent);
+    }
+}
+
+/// Status of a transaction given up on: dropped only if the node knows none of its broadcasts and
+/// doesn't count its nonce as pending, it may still be mined otherwise.
+async fn unmined_status<M: Middleware>(client: &M, hashes: &[H256], from: Address, nonce: U256) -> Result<TxStatus, Error> {
+    for hash in hashes {
+        if client.get_transaction(*hash).await.map_err(client_error)?.is_some() {
+            return Ok(TxStatus::Pending);
+        }
+    }
+    let pending = client
+        .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+        .await
+        .map_err(client_error)?;
+    if pending <= nonce {
+        Ok(TxStatus::Dropped)
+    } else {
+        Ok(TxStatus::Pending)
+    }
+}
+
+async fn receipt<M: Middleware>(client: &M, hashes: &[H256]) -> Result<Option<TransactionReceipt>, Error> {
+    for hash in hashes.iter().rev() {
+        if let Some(receipt) = client.get_transaction_receipt(*hash).await.map_err(client_error)? {
+            return Ok(Some(receipt));
+        }
+    }
+    Ok(None)
+}
+
+async fn wait<M: Middleware>(client: &M, hashes: &[H256], from: Address, nonce: U256, policy: &TxPolicy) -> Result<Wait, Error> {
+    let deadline = Instant::now() + policy.stuck_timeout;
+    loop {
+        // read the nonce first, a broadcast mined after it has a receipt below
+        let mined_nonce = client.get_transaction_count(from, None).await.map_err(client_error)?;
+        match receipt(client, hashes).await? {
+            Some(receipt) if receipt.status.map(|s| s.is_zero()).unwrap_or(false) => {
+                return Ok(Wait::Done(TxOutcome {
+                    tx_hash: receipt.transaction_hash,
+                    status: TxStatus::Reverted,
+                    block: receipt.block_number.map(|b| b.as_u64()),
+                }));
+            }
+            Some(receipt) => {
+                let block = receipt.block_number.map(|b| b.as_u64()).unwrap_or_default();
+                let head = client.get_block_number().await.map_err(client_error)?.as_u64();
+                if head + 1 >= block + policy.confirmations {
+                    return Ok(Wait::Done(TxOutcome {
+                        tx_hash: receipt.transaction_hash,
+                        status: TxStatus::Confirmed,
+                        block: Some(block),
+                    }));
+                }
+                // mined, wait for the confirmations whatever the deadline, a reorg drops the receipt
+                tokio::time::sleep(policy.poll_interval).await;
+                continue;
+            }
+            None if mined_nonce > nonce => {
+                return Ok(Wait::Done(TxOutcome {
+                    tx_hash: *hashes.last().unwrap(),
+                    status: TxStatus::Replaced,
+                    block: None,
+                }));
+            }
+            None => {}
+        }
+        let known = client.get_transaction(*hashes.last().unwrap()).await.map_err(client_error)?.is_some();
+        if !known || Instant::now() >= deadline {
+            return Ok(Wait::Stuck);
+        }
+        tokio::time::sleep(policy.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Eip1559TransactionRequest, Transaction, TransactionRequest, U64};
+
+    const NONCE: u64 = 5;
+
+    fn policy() -> TxPolicy {
+        TxPolicy {
+            confirmations: 1,
+            stuck_timeout: Duration::from_secs(60),
+            max_rebroadcasts: 1,
+            gas_bump_percent: 10,
+            poll_interval: Duration::ZERO,
+        }
+    }
+
+    fn receipt(hash: H256, status: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: hash,
+            status: Some(U64::from(status)),
+            block_number: Some(U64::from(10)),
+            ..Default::default()
+        }
+    }
+
+    // the mock answers the last pushed response first
+    fn mocked(responses: Vec<serde_json::Value>) -> Provider<MockProvider> {
+        let (provider, mock) = Provider::mocked();
+        for response in responses.into_iter().rev() {
+            mock.push::<serde_json::Value, _>(response).unwrap();
+        }
+        provider
+    }
+
+    fn json<T: serde::Serialize>(value: T) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    async fn wait_outcome(provider: &Provider<MockProvider>, hashes: &[H256]) -> Option<TxOutcome> {
+        match wait(provider, hashes, Address::zero(), NONCE.into(), &policy()).await.unwrap() {
+            Wait::Done(outcome) => Some(outcome),
+            Wait::Stuck => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_reverted() {
+        let hash = H256::from_low_u64_be(1);
+        let provider = mocked(vec![json(U256::from(NONCE + 1)), json(receipt(hash, 0))]);
+        let outcome = wait_outcome(&provider, &[hash]).await.unwrap();
+        assert_eq!(outcome.status, TxStatus::Reverted);
+        assert_eq!((outcome.tx_hash, outcome.block), (hash, Some(10)));
+    }
+
+    #[tokio::test]
+    async fn test_wait_confirmed() {
+        let (first, second) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        // the re-broadcast wasn't mined but the first broadcast was
+        let provider = mocked(vec![
+            json(U256::from(NONCE + 1)),
+            json(Option::<TransactionReceipt>::None),
+            json(receipt(first, 1)),
+            json(U64::from(10)),
+        ]);
+        let outcome = wait_outcome(&provider, &[first, second]).await.unwrap();
+        assert_eq!((outcome.status, outcome.tx_hash), (TxStatus::Confirmed, first));
+    }
+
+    #[tokio::test]
+    async fn test_wait_replaced() {
+        let hash = H256::from_low_u64_be(1);
+        let provider = mocked(vec![json(U256::from(NONCE + 1)), json(Option::<TransactionReceipt>::None)]);
+        let outcome = wait_outcome(&provider, &[hash]).await.unwrap();
+        assert_eq!(outcome.status, TxStatus::Replaced);
+    }
+
+    #[tokio::test]
+    async fn test_wait_dropped() {
+        let hash = H256::from_low_u64_be(1);
+        // not mined and forgotten by the node
+        let provider = mocked(vec![
+            json(U256::from(NONCE)),
+            json(Option::<TransactionReceipt>::None),
+            json(Option::<Transaction>::None),
+        ]);
+        assert!(wait_outcome(&provider, &[hash]).await.is_none());
+
+        let provider = mocked(vec![json(Option::<Transaction>::None), json(U256::from(NONCE))]);
+        let status = unmined_status(&provider, &[hash], Address::zero(), NONCE.into()).await.unwrap();
+        assert_eq!(status, TxStatus::Dropped);
+        // the node forgot the broadcast but counts the nonce, it may still be mined
+        let provider = mocked(vec![json(Option::<Transaction>::None), json(U256::from(NONCE + 1))]);
+        let status = unmined_status(&provider, &[hash], Address::zero(), NONCE.into()).await.unwrap();
+        assert_eq!(status, TxStatus::Pending);
+        let provider = mocked(vec![json(Transaction { hash, ..Default::default() })]);
+        let status = unmined_status(&provider, &[hash], Address::zero(), NONCE.into()).await.unwrap();
+        assert_eq!(status, TxStatus::Pending);
+    }
+
+    #[test]
+    fn test_bump_gas() {
+        let mut legacy: TypedTransaction = TransactionRequest::new().gas_price(100).into();
+        bump_gas(&mut legacy, 20);
+        assert_eq!(legacy.gas_price(), Some(U256::from(121)));
+
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .max_fee_per_gas(200)
+            .max_priority_fee_per_gas(10)
+            .into();
+        bump_gas(&mut tx, 10);
+        match tx {
+            TypedTransaction::Eip1559(inner) => {
+                assert_eq!(inner.max_fee_per_gas, Some(U256::from(221)));
+                assert_eq!(inner.max_priority_fee_per_gas, Some(U256::from(12)));
+            }
+            _ => panic!("transaction type changed"),
+        }
+    }
+}
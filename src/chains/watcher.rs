@@ -0,0 +1,390 @@
+use super::contract::{missing_bindings, to_u64, Contract, InferenceContract, InferenceRequestedFilter, INFERENCECONTRACT_ABI, INFERENCEREGISTRYCONTRACT_ABI, INFERENCE_REGISTRY_CONTRACTS};
+use crate::node::aizel::InferenceRequest;
+use crate::node::config::{backfill_batch, checkpoint_path, NETWORK_CONFIGS, WATCHER_RETRY_SECS};
+use common::error::Error;
+use ethers::{
+    contract::LogMeta,
+    providers::{Middleware, Provider, Ws},
+    types::H256,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
+use tonic::{Code, Status};
+
+/// A request handed over by a watcher, along with where to report whether it was accepted.
+pub type Delivery = (InferenceRequest, oneshot::Sender<Result<(), Status>>);
+
+/// First log position of a network that wasn't handed over yet.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Checkpoint {
+    pub block: u64,
+    pub log_index: u64,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        serde_json::from_str(&content).map(Some).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse checkpoint {}", e),
+        })
+    }
+
+    /// Replaces the stored checkpoint atomically, a crash never leaves a torn file behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let file_error = |e: std::io::Error| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(file_error)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string(self).unwrap()).map_err(file_error)?;
+        std::fs::rename(&tmp, path).map_err(file_error)
+    }
+
+    fn after(meta: &LogMeta) -> Self {
+        Checkpoint {
+            block: meta.block_number.as_u64(),
+            log_index: meta.log_index.as_u64() + 1,
+        }
+    }
+
+    fn seen(&self, meta: &LogMeta) -> bool {
+        Self::after(meta) <= *self
+    }
+}
+
+pub fn to_request(event: &InferenceRequestedFilter, network: &str) -> Result<InferenceRequest, Error> {
+    Ok(InferenceRequest {
+        request_id: to_u64(event.request_id, "request_id")?,
+        model_id: to_u64(event.model_id, "model_id")?,
+        input: event.input.clone(),
+        user_pk: event.user_pk.clone(),
+        req_type: event.req_type as i32,
+        network: network.to_string(),
+    })
+}
+
+/// Follows the requests addressed to the node on the inference contract of a network, so they
+/// are served even if the gate never pushes them. Requests are handed over in chain order and the
+/// position of the last one is persisted, the blocks missed while the node was down are backfilled
+/// from it on restart.
+pub struct ChainWatcher {
+    network: String,
+    public_key: String,
+    checkpoint_path: PathBuf,
+    sink: Sender<Delivery>,
+}
+
+impl ChainWatcher {
+    pub fn new(network: &str, public_key: String, sink: Sender<Delivery>) -> Self {
+        Self {
+            network: network.to_string(),
+            public_key,
+            checkpoint_path: checkpoint_path(network),
+            sink,
+        }
+    }
+
+    /// Watches until the sink is closed, reconnecting whenever the subscription drops. Gives up if the
+    /// contracts don't implement what the watcher relies on, it would never see a request otherwise.
+    pub async fn run(self) {
+        loop {
+            match self.missing_bindings().await {
+                Ok(missing) if missing.is_empty() => break,
+                Ok(missing) => {
+                    error!(
+                        "chain watcher of network {} disabled, the contracts don't implement {}",
+                        self.network,
+                        missing.join(", ")
+                    );
+                    return;
+                }
+                Err(e) => {
+                    warn!("failed to check the contracts of network {}: {}", self.network, e);
+                    tokio::time::sleep(Duration::from_secs(WATCHER_RETRY_SECS)).await;
+                }
+            }
+        }
+        loop {
+            match self.watch().await {
+                Ok(_) => return,
+                Err(e) => {
+                    error!("chain watcher of network {} stopped: {}", self.network, e);
+                    tokio::time::sleep(Duration::from_secs(WATCHER_RETRY_SECS)).await;
+                }
+            }
+        }
+    }
+
+    async fn node_id(&self) -> u64 {
+        // the node registers once its server is up, wait for it
+        loop {
+            match Contract::query_node_id(self.public_key.clone(), &self.network).await {
+                Ok(id) if id != 0 => return id,
+                Ok(_) => info!("node is not registered on network {} yet", self.network),
+                Err(e) => warn!("failed to query node id on network {}: {}", self.network, e),
+            }
+            tokio::time::sleep(Duration::from_secs(WATCHER_RETRY_SECS)).await;
+        }
+    }
+
+    async fn missing_bindings(&self) -> Result<Vec<String>, Error> {
+        let contract = self.connect().await?;
+        let mut missing = missing_bindings(contract.client_ref(), contract.address(), &INFERENCECONTRACT_ABI, &[], &["InferenceRequested"]).await?;
+        let registry = INFERENCE_REGISTRY_CONTRACTS
+            .get(&self.network)
+            .ok_or(Error::NetworkConfigNotFoundError { network: self.network.clone() })?;
+        missing.extend(missing_bindings(registry.client_ref(), registry.address(), &INFERENCEREGISTRYCONTRACT_ABI, &["getNodeId"], &[]).await?);
+        Ok(missing)
+    }
+
+    async fn connect(&self) -> Result<InferenceContract<Provider<Ws>>, Error> {
+        let config = NETWORK_CONFIGS
+            .get()
+            .unwrap()
+            .iter()
+            .find(|c| c.network == self.network)
+            .ok_or(Error::NetworkConfigNotFoundError { network: self.network.clone() })?;
+        let url = config.ws_endpoint();
+        let provider = Provider::<Ws>::connect(&url).await.map_err(|e| Error::NetworkError {
+            address: url.clone(),
+            message: e.to_string(),
+        })?;
+        let address = config
+            .contracts
+            .iter()
+            .find(|a| a.name == "INFERENCE")
+            .ok_or(Error::NetworkConfigNotFoundError { network: self.network.clone() })?
+            .address;
+        Ok(InferenceContract::new(address, Arc::new(provider)))
+    }
+
+    /// Hands the request over unless it was seen before and moves the checkpoint past it once it is
+    /// persisted or rejected for good. Returns false once the sink is closed, and an error if the
+    /// request has to be handed over again, e.g. the queue is full.
+    async fn deliver(&self, event: InferenceRequestedFilter, meta: LogMeta, checkpoint: &mut Checkpoint) -> Result<bool, Error> {
+        if checkpoint.seen(&meta) {
+            return Ok(true);
+        }
+        match to_request(&event, &self.network) {
+            Ok(req) => {
+                let request_id = req.request_id;
+                let (ack, accepted) = oneshot::channel();
+                if self.sink.send((req, ack)).await.is_err() {
+                    return Ok(false);
+                }
+                match accepted.await {
+                    Err(_) => return Ok(false),
+                    Ok(Err(status)) if matches!(status.code(), Code::ResourceExhausted | Code::Internal | Code::Unavailable) => {
+                        return Err(Error::ServerError {
+                            message: format!("request {} not accepted: {}", request_id, status.message()),
+                        });
+                    }
+                    Ok(_) => {}
+                }
+            }
+            // can never be served
+            Err(e) => warn!("skip request {} of network {}: {}", event.request_id, self.network, e),
+        }
+        *checkpoint = Checkpoint::after(&meta);
+        checkpoint.save(&self.checkpoint_path)?;
+        Ok(true)
+    }
+
+    async fn watch(&self) -> Result<(), Error> {
+        let node_id = self.node_id().await;
+        let contract = self.connect().await?;
+        let contract_error = |e: ethers::contract::ContractError<Provider<Ws>>| Error::ContractError { message: e.to_string() };
+        let filter = || contract.inference_requested_filter().topic2(H256::from_low_u64_be(node_id));
+
+        // subscribe before backfilling so nothing emitted in between is missed, the checkpoint
+        // drops what both of them return
+        let subscription = filter();
+        let mut stream = subscription.subscribe_with_meta().await.map_err(contract_error)?;
+        let head = contract
+            .client()
+            .get_block_number()
+            .await
+            .map_err(|e| Error::NetworkError {
+                address: self.network.clone(),
+                message: e.to_string(),
+            })?
+            .as_u64();
+        // a node without checkpoint starts from the head instead of the whole history
+        let mut checkpoint = Checkpoint::load(&self.checkpoint_path)?.unwrap_or(Checkpoint {
+            block: head + 1,
+            log_index: 0,
+        });
+        if checkpoint.block <= head {
+            info!("backfill network {} from block {} to {}", self.network, checkpoint.block, head);
+        }
+        let mut from = checkpoint.block;
+        while from <= head {
+            let to = (from + backfill_batch() - 1).min(head);
+            let events = filter().from_block(from).to_block(to).query_with_meta().await.map_err(contract_error)?;
+            for (event, meta) in events {
+                if !self.deliver(event, meta, &mut checkpoint).await? {
+                    return Ok(());
+                }
+            }
+            from = to + 1;
+        }
+        if checkpoint.block <= head {
+            checkpoint = Checkpoint {
+                block: head + 1,
+                log_index: 0,
+            };
+            checkpoint.save(&self.checkpoint_path)?;
+        }
+
+        info!("watch requests of node {} on network {}", node_id, self.network);
+        while let Some(item) = stream.next().await {
+            let (event, meta) = item.map_err(contract_error)?;
+            if !self.deliver(event, meta, &mut checkpoint).await? {
+                return Ok(());
+            }
+        }
+        Err(Error::NetworkError {
+            address: self.network.clone(),
+            message: "event subscription closed".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Address, U256, U64};
+
+    fn meta(block: u64, log_index: u64) -> LogMeta {
+        LogMeta {
+            address: Address::zero(),
+            block_number: U64::from(block),
+            block_hash: H256::zero(),
+            transaction_hash: H256::zero(),
+            transaction_index: U64::zero(),
+            log_index: U256::from(log_index),
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path = std::env::temp_dir().join(format!("aizel_checkpoint_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        let checkpoint = Checkpoint::after(&meta(10, 2));
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(checkpoint));
+        assert!(checkpoint.seen(&meta(9, 5)));
+        assert!(checkpoint.seen(&meta(10, 2)));
+        assert!(!checkpoint.seen(&meta(10, 3)));
+        assert!(!checkpoint.seen(&meta(11, 0)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn event(request_id: u64) -> InferenceRequestedFilter {
+        InferenceRequestedFilter {
+            request_id: U256::from(request_id),
+            node_id: U256::from(1),
+            model_id: U256::from(3),
+            user_pk: "pk".to_string(),
+            input: "input".to_string(),
+            req_type: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let path = std::env::temp_dir().join(format!("aizel_deliver_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (sink, mut rx) = tokio::sync::mpsc::channel::<Delivery>(1);
+        let watcher = ChainWatcher {
+            network: "aizel".to_string(),
+            public_key: String::new(),
+            checkpoint_path: path.clone(),
+            sink,
+        };
+        tokio::spawn(async move {
+            let acks = [
+                Err(Status::resource_exhausted("queue is full")),
+                Ok(()),
+                Err(Status::already_exists("request is already Queued")),
+            ];
+            for result in acks {
+                let (_, ack) = rx.recv().await.unwrap();
+                ack.send(result).unwrap();
+            }
+        });
+        let mut checkpoint = Checkpoint { block: 0, log_index: 0 };
+        // handed over again later, the checkpoint stays before it
+        assert!(watcher.deliver(event(7), meta(10, 2), &mut checkpoint).await.is_err());
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+        assert!(watcher.deliver(event(7), meta(10, 2), &mut checkpoint).await.unwrap());
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(Checkpoint::after(&meta(10, 2))));
+        // known requests are skipped for good
+        assert!(watcher.deliver(event(8), meta(11, 0), &mut checkpoint).await.unwrap());
+        assert_eq!(checkpoint, Checkpoint::after(&meta(11, 0)));
+        // the intake is gone
+        assert!(!watcher.deliver(event(9), meta(12, 0), &mut checkpoint).await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_log() {
+        use ethers::types::{Bytes, Log};
+        use ethers::utils::keccak256;
+        // InferenceRequested(7, 1, 2, "pk", "ciphertext", 1), encoded after the signature the node expects
+        let data = [
+            format!("{:064x}", 2),
+            format!("{:064x}", 0x80),
+            format!("{:064x}", 0xc0),
+            format!("{:064x}", 1),
+            format!("{:064x}", 2),
+            format!("{:0<64}", hex::encode("pk")),
+            format!("{:064x}", 10),
+            format!("{:0<64}", hex::encode("ciphertext")),
+        ]
+        .concat();
+        let log = Log {
+            topics: vec![
+                H256::from(keccak256("InferenceRequested(uint256,uint256,uint256,string,string,uint8)")),
+                H256::from_low_u64_be(7),
+                H256::from_low_u64_be(1),
+            ],
+            data: Bytes::from(hex::decode(data).unwrap()),
+            ..Default::default()
+        };
+        let event: InferenceRequestedFilter = ethers::contract::parse_log(log).unwrap();
+        assert_eq!(event.node_id, U256::from(1));
+        let req = to_request(&event, "aizel").unwrap();
+        assert_eq!((req.request_id, req.model_id, req.req_type), (7, 2, 1));
+        assert_eq!((req.user_pk.as_str(), req.input.as_str()), ("pk", "ciphertext"));
+    }
+
+    #[test]
+    fn test_to_request() {
+        let req = to_request(&event(7), "aizel").unwrap();
+        assert_eq!((req.request_id, req.model_id, req.req_type), (7, 3, 1));
+        assert_eq!((req.user_pk.as_str(), req.network.as_str()), ("pk", "aizel"));
+        let overflow = InferenceRequestedFilter {
+            request_id: U256::from(u64::MAX) + 1,
+            ..event(7)
+        };
+        assert!(matches!(to_request(&overflow, "aizel"), Err(Error::InvalidArgumentError { .. })));
+    }
+}
@@ -3,3 +3,4 @@ pub mod crypto;
 pub mod node;
 pub mod s3_minio;
 pub mod tee;
+pub mod verification;
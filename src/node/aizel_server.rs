@@ -3,38 +3,114 @@ mod aizel {
 }
 use super::aizel::gate_service_client::GateServiceClient;
 use super::aizel::inference_server::Inference;
-use super::aizel::{InferenceRequest, InferenceResponse};
+use super::aizel::{InferenceChunk, InferenceRequest, InferenceResponse, RequestState, RequestStatusRequest, RequestStatusResponse};
 use super::aizel::UploadOutputRequest;
-use super::config::{data_node_id, AIZEL_CONFIG, DEFAULT_CHANNEL_SIZE, INPUT_BUCKET, TRANSFER_AGENT_ID};
-use super::model_client::{ChatClient, TransferAgentClient, MlClient};
-use super::model_server::MlServer;
+use super::backend::{create_backends, remote_backends, select_backend, Backends, ModelBackend, LLAMA_BACKEND};
+use super::config::{attestation_enabled, chain_watcher_enabled, data_node_id, inference_workers, queue_capacity, queue_log_path, schedule_max_wait, schedule_policy, AIZEL_CONFIG, DEFAULT_CHANNEL_SIZE, INPUT_BUCKET};
+use super::request_log::RequestLog;
+use super::request_registry::RequestRegistry;
+use super::scheduler::Scheduler;
+use super::supervisor::{supervise, NodeHealth};
+use super::validation::{validation_enabled, RequestValidator};
 use crate::chains::contract::Contract;
-use crate::chains::contract::ModelInfo;
-use crate::chains::ethereum::pubkey_to_address;
+use crate::chains::ethereum::parse_pubkey;
+use crate::chains::tx_manager::{TxOutcome, TxStatus};
+use crate::chains::watcher::ChainWatcher;
 use crate::crypto::digest::Digest;
 use crate::crypto::elgamal::{Ciphertext, Elgamal};
 use crate::crypto::secret::Secret;
-use crate::node::model_server::LlamaServer;
 use crate::s3_minio::client::MinioClient;
 use crate::tee::attestation::AttestationAgent;
 use common::error::Error;
+use common::tee::report_data::ReportData;
 use ethers::core::{
     abi::{self, Token},
     utils,
 };
-use log::error;
-use secp256k1::{PublicKey, SecretKey};
-use serde::{Deserialize, Serialize};
+use log::{error, info};
+use secp256k1::SecretKey;
+use serde::Deserialize;
 use tokio::sync::mpsc::{channel, Sender};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 use std::collections::HashMap;
+use std::sync::Arc;
 pub struct AizelInference {
     pub secret: Secret,
-    senders: HashMap<String, Sender<InferenceRequest>>,
+    intake: Intake,
+    health: Arc<NodeHealth>,
+    // not set when the requests are trusted, e.g. on a local chain
+    validator: Option<RequestValidator>,
+}
+
+/// Entry of the network queues, shared by the gRPC service and the chain watchers.
+#[derive(Clone)]
+struct Intake {
+    schedulers: HashMap<String, Arc<Scheduler<InferenceTask>>>,
+    logs: HashMap<String, Arc<RequestLog>>,
+    registry: Arc<RequestRegistry>,
+}
+
+impl Intake {
+    /// Persists and queues a request unless it is already known. A failed request may be retried.
+    #[allow(clippy::result_large_err)]
+    fn accept(&self, req: InferenceRequest, stream: Option<ChunkSender>) -> Result<(), Status> {
+        let scheduler = self.schedulers.get(&req.network).ok_or(Status::internal(format!("unkown network argument {}", req.network)))?;
+        let log = self.logs.get(&req.network).ok_or(Status::internal(format!("unkown network argument {}", req.network)))?;
+        // the output is encrypted to the key, a request it isn't valid for can't be answered
+        parse_pubkey(&req.user_pk).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        if let Err(state) = self.registry.try_queue(&req.network, req.request_id) {
+            return Err(Status::already_exists(format!(
+                "request {} of network {} is already {:?}",
+                req.request_id, req.network, state
+            )));
+        }
+        let full = || Status::resource_exhausted(format!("queue of network {} is full", req.network));
+        if scheduler.is_full() {
+            let status = full();
+            self.registry.fail(&req.network, req.request_id, status.message().to_string());
+            return Err(status);
+        }
+        if let Err(e) = log.append(&req) {
+            let status = Status::internal(format!("failed to persist request {}", e));
+            self.registry.fail(&req.network, req.request_id, status.message().to_string());
+            return Err(status);
+        }
+        let status = full();
+        if let Err(task) = (InferenceTask { req, stream }).schedule(scheduler) {
+            // filled up since the check, the request is dropped as if it was never accepted
+            self.registry.fail(&task.req.network, task.req.request_id, status.message().to_string());
+            AizelInference::complete(log, task.req.request_id);
+            return Err(status);
+        }
+        Ok(())
+    }
 }
 
 type Hash = [u8; 32];
 
+type ChunkSender = Sender<Result<InferenceChunk, Status>>;
+
+/// A queued request, with the stream its output is forwarded to when the caller asked for streaming.
+struct InferenceTask {
+    req: InferenceRequest,
+    stream: Option<ChunkSender>,
+}
+
+impl InferenceTask {
+    fn key(&self) -> u64 {
+        self.req.model_id
+    }
+
+    fn schedule(self, scheduler: &Scheduler<InferenceTask>) -> Result<(), InferenceTask> {
+        scheduler.push(self.key(), self)
+    }
+
+    fn restore(self, scheduler: &Scheduler<InferenceTask>) {
+        scheduler.restore(self.key(), self)
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 struct ModelServiceResponse {
@@ -50,113 +126,286 @@ struct InferenceOutput {
     pub report: String,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct TransferInfo {
-    to: String,
-    token: String,
-    amount: u64,
-}
-
 #[tonic::async_trait]
 impl Inference for AizelInference {
     async fn llama_inference(
         &self,
         request: Request<InferenceRequest>,
     ) -> Result<Response<InferenceResponse>, Status> {
-        let req = request.into_inner();
-        let sender = self.senders.get(&req.network).ok_or(Status::internal(format!("unkown network argument {}", req.network)))?;
-        sender.send(req).await.map_err(|e| {
-            Status::internal(format!("failed to process request {}", e.to_string()))
-        })?;
+        self.enqueue(request.into_inner(), None).await?;
         Ok(Response::new(InferenceResponse {
             output: String::new(),
         }))
     }
+
+    type StreamInferenceStream = ReceiverStream<Result<InferenceChunk, Status>>;
+
+    async fn stream_inference(
+        &self,
+        request: Request<InferenceRequest>,
+    ) -> Result<Response<Self::StreamInferenceStream>, Status> {
+        let (tx, rx) = channel(DEFAULT_CHANNEL_SIZE);
+        self.enqueue(request.into_inner(), Some(tx)).await?;
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_request_status(
+        &self,
+        request: Request<RequestStatusRequest>,
+    ) -> Result<Response<RequestStatusResponse>, Status> {
+        let req = request.into_inner();
+        let status = self.intake.registry.status(&req.network, req.request_id).ok_or(Status::not_found(format!(
+            "request {} not found in network {}",
+            req.request_id, req.network
+        )))?;
+        Ok(Response::new(status))
+    }
+}
+
+/// Everything the workers of a network share. Requests hold a read lease on their backend while
+/// they run, so several of them can use the loaded model at once and a model switch waits for them.
+struct NetworkWorker {
+    secret: Secret,
+    agent: AttestationAgent,
+    registry: Arc<RequestRegistry>,
+    log: Arc<RequestLog>,
+    backends: Backends,
+}
+
+impl NetworkWorker {
+    /// Whether the model is resident in one of the backends, the scheduler favours its requests.
+    fn is_resident(&self, model_id: u64) -> bool {
+        self.backends.values().any(|pool| pool.is_resident(model_id))
+    }
+
+    async fn handle(self: &Arc<Self>, task: InferenceTask) {
+        let InferenceTask { req, stream } = task;
+        let model_info = match Contract::query_model(req.model_id, &req.network).await {
+            Ok(model_info) => model_info,
+            Err(e) => {
+                error!("failed to query model from contract {}", e.to_string());
+                self.fail(&req, &stream, e).await;
+                return;
+            }
+        };
+
+        let name = select_backend(&req, &model_info, remote_backends());
+        let backend = match self.backends.get(name) {
+            Some(backend) => backend,
+            None => {
+                let e = Error::InferenceError {
+                    message: format!("backend {} not found in network {}", name, req.network),
+                };
+                self.fail(&req, &stream, e).await;
+                return;
+            }
+        };
+
+        self.registry.transition(&req.network, req.request_id, RequestState::ModelLoading);
+        let result = match backend.acquire(&model_info).await {
+            Ok(lease) => self.process(&req, &*lease, &stream).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(output) => {
+                if let Some(stream) = &stream {
+                    let _ = stream.send(Ok(InferenceChunk {
+                        output: output.output.clone(),
+                        finished: true,
+                    })).await;
+                }
+                let worker = self.clone();
+                tokio::spawn(async move {
+                    worker.submit(&req, output).await;
+                });
+            }
+            Err(e) => {
+                error!(
+                    "failed to process the request {}: {}",
+                    req.request_id,
+                    e.to_string()
+                );
+                self.fail(&req, &stream, e).await;
+            }
+        }
+    }
+
+    async fn process(&self, req: &InferenceRequest, backend: &dyn ModelBackend, stream: &Option<ChunkSender>) -> Result<InferenceOutput, Error> {
+        AizelInference::process_inference(req, self.secret.clone(), &self.agent, backend, &self.registry, stream).await
+    }
+
+    async fn submit(&self, req: &InferenceRequest, output: InferenceOutput) {
+        self.registry.transition(&req.network, req.request_id, RequestState::Uploading);
+        // submit output to gate server
+        let (output_hash, report_hash) =
+        match AizelInference::submit_output(output.output, output.report).await {
+            Ok(hashes) => hashes,
+            Err(e) => {
+                error!("failed to upload output of request {}: {}", req.request_id, e.to_string());
+                self.registry.fail(&req.network, req.request_id, e.to_string());
+                return;
+            }
+        };
+        self.registry.transition(&req.network, req.request_id, RequestState::Submitting);
+        match Contract::submit_inference(
+            req.request_id,
+            output_hash,
+            report_hash,
+            &req.network
+        )
+        .await {
+            Ok(outcome) => {
+                self.registry.set_tx(&req.network, req.request_id, format!("{:?}", outcome.tx_hash), outcome.status.to_string());
+                match outcome.status {
+                    TxStatus::Confirmed => {
+                        self.registry.transition(&req.network, req.request_id, RequestState::Submitted);
+                        AizelInference::complete(&self.log, req.request_id);
+                    }
+                    // may still be mined, the request stays submitting so it isn't accepted and submitted again
+                    TxStatus::Pending => AizelInference::settle(&self.log, req.request_id, TxStatus::Pending),
+                    status => {
+                        self.registry.fail(&req.network, req.request_id, format!("result tx {:?} {}", outcome.tx_hash, status));
+                        AizelInference::settle(&self.log, req.request_id, status);
+                    }
+                }
+            }
+            Err(e) => {
+                self.registry.fail(&req.network, req.request_id, e.to_string());
+            }
+        }
+    }
+
+    /// Notifies the caller and submits the error in the background, a worker doesn't wait for the
+    /// transaction to be final.
+    async fn fail(self: &Arc<Self>, req: &InferenceRequest, stream: &Option<ChunkSender>, e: Error) {
+        AizelInference::notify_error(stream, &e).await;
+        let (worker, req) = (self.clone(), req.clone());
+        tokio::spawn(async move {
+            AizelInference::handle_error(&req, &worker.secret, e, &worker.agent, &worker.registry, &worker.log).await;
+        });
+    }
 }
 
 impl AizelInference {
+    async fn enqueue(&self, req: InferenceRequest, stream: Option<ChunkSender>) -> Result<(), Status> {
+        if !self.health.is_healthy(&req.network) {
+            return Err(Status::unavailable(format!("model servers of network {} are restarting", req.network)));
+        }
+        if let Some(validator) = &self.validator {
+            validator.validate(&req).await?;
+        }
+        self.intake.accept(req, stream)
+    }
+
     pub async fn new(secret: Secret) -> Self {
-        let (txs, rxs): (Vec<_>, Vec<_>) = AIZEL_CONFIG.networks.iter().map(|n| {
-            let (tx, rx) = channel::<InferenceRequest>(DEFAULT_CHANNEL_SIZE);
-            ((n.clone(), tx), (n.clone(), rx) )
+        let schedulers: HashMap<_, _> = AIZEL_CONFIG.networks.iter().map(|n| {
+            (n.clone(), Arc::new(Scheduler::new(schedule_policy(), schedule_max_wait(), queue_capacity())))
+        }).collect();
+
+        let (logs, replays): (HashMap<_, _>, Vec<_>) = AIZEL_CONFIG.networks.iter().map(|n| {
+            let (log, pending) = RequestLog::open(queue_log_path(n)).unwrap();
+            ((n.clone(), Arc::new(log)), (n.clone(), pending))
         }).unzip();
 
+        let registry = Arc::new(RequestRegistry::new());
+        let health = Arc::new(NodeHealth::new());
         let aizel_inference: AizelInference = Self {
             secret: secret.clone(),
-            senders: txs.into_iter().map(|x| {
-                x
-            }).collect(),
+            intake: Intake {
+                schedulers,
+                logs,
+                registry: registry.clone(),
+            },
+            health: health.clone(),
+            validator: validation_enabled()
+                .await
+                .map_err(|e| {
+                    error!("failed to set up request validation {}", e);
+                    e
+                })
+                .unwrap()
+                .then(|| RequestValidator::new(secret.name.encode())),
         };
 
-        for (network, mut rx) in rxs {
-            
+        for (network, scheduler) in aizel_inference.intake.schedulers.iter() {
+            let network = network.clone();
             let data_node_id = data_node_id(&network).unwrap();
             let default_model = Contract::query_data_node_default_model(data_node_id, &network).await.unwrap();
-            let mut llama_cpp_server = LlamaServer::new(&default_model).await.unwrap();
-            let mut ml_server = MlServer::new(&None).await.unwrap();
-            let secret = secret.clone();
-            tokio::spawn(async move {
-                let agent = AttestationAgent::new()
-                    .await
-                    .map_err(|e| {
-                        error!("failed to create attestation agent {}", e);
-                        e
-                    })
-                    .unwrap();
-                while let Some(req) = rx.recv().await {
-                    let model_info = Contract::query_model(req.model_id, &req.network).await;
-                    match model_info {
-                        Ok(model_info) => {
-                            if req.req_type == aizel::InferenceType::AizelModel as i32 {
-                                match ml_server.run(&model_info).await {
-                                    Err(e) => {
-                                        error!("failed to run model {}", e.to_string());
-                                        continue;
-                                    }
-                                    Ok(()) => {}
-                                }
-                            } else {
-                                match llama_cpp_server.run(&model_info).await {
-                                    Err(e) => {
-                                        error!("failed to run model {}", e.to_string());
-                                        continue;
-                                    }
-                                    Ok(()) => {}
-                                }
-                            }
-                            match AizelInference::process_inference(&req, secret.clone(), &agent, &model_info).await
-                            {
-                                Ok(output) => {
-                                    tokio::spawn(async move {
-                                        // submit output to gate server
-                                        let (output_hash, report_hash) =
-                                        AizelInference::submit_output(output.output, output.report)
-                                            .await
-                                            .unwrap();
-                                        let _ = Contract::submit_inference(
-                                            req.request_id,
-                                            output_hash,
-                                            report_hash,
-                                            &req.network
-                                        )
-                                        .await;
-                                    });
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "failed to process the request {}: {}",
-                                        req.request_id,
-                                        e.to_string()
-                                    );
-                                    AizelInference::handle_error(&req, e, &agent).await;
-                                }
-                            };
-                        }
-                        Err(e) => {
-                            error!("failed to query model from contract {}", e.to_string());
-                            AizelInference::handle_error(&req, e, &agent).await;
+            let agent = AttestationAgent::new(&secret)
+                .await
+                .map_err(|e| {
+                    error!("failed to create attestation agent {}", e);
+                    e
+                })
+                .unwrap();
+            let worker = Arc::new(NetworkWorker {
+                secret: secret.clone(),
+                agent,
+                registry: registry.clone(),
+                log: aizel_inference.intake.logs.get(&network).unwrap().clone(),
+                backends: create_backends(&network).unwrap(),
+            });
+            // serve the default model of the data node before accepting requests
+            drop(worker.backends.get(LLAMA_BACKEND).unwrap().acquire(&default_model).await.unwrap());
+            for (name, pool) in worker.backends.iter() {
+                for slot in 0..pool.slots().len() {
+                    let server = if pool.slots().len() > 1 { format!("{}-{}", name, slot) } else { name.clone() };
+                    let (worker, health, network, name) = (worker.clone(), health.clone(), network.clone(), name.clone());
+                    tokio::spawn(async move {
+                        let slot = &worker.backends.get(&name).unwrap().slots()[slot];
+                        supervise(&**slot, &network, &server, &health).await;
+                    });
+                }
+            }
+            let workers = inference_workers();
+            info!("start {} inference workers for network {} with {:?} scheduling", workers, network, schedule_policy());
+            for _ in 0..workers {
+                let worker = worker.clone();
+                let scheduler = scheduler.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let task = scheduler.pop(|model_id| worker.is_resident(model_id)).await;
+                        let (network, request_id) = (task.req.network.clone(), task.req.request_id);
+                        // a panic is contained to its request, the worker goes on with the next one
+                        let handled = {
+                            let worker = worker.clone();
+                            tokio::spawn(async move { worker.handle(task).await })
+                        };
+                        if let Err(e) = handled.await {
+                            error!("worker panicked on request {} of network {}: {}", request_id, network, e);
+                            worker.registry.fail(&network, request_id, "internal error".to_string());
                         }
                     }
+                });
+            }
+        }
+
+        // queue again the requests that were accepted but not settled before the last shutdown
+        for (network, pending) in replays {
+            let scheduler = aizel_inference.intake.schedulers.get(&network).unwrap();
+            for req in pending {
+                registry.queue(&req.network, req.request_id);
+                InferenceTask { req, stream: None }.restore(scheduler);
+            }
+        }
+
+        if chain_watcher_enabled() {
+            let (tx, mut rx) = channel(DEFAULT_CHANNEL_SIZE);
+            for network in AIZEL_CONFIG.networks.iter() {
+                tokio::spawn(ChainWatcher::new(network, secret.name.encode(), tx.clone()).run());
+            }
+            let intake = aizel_inference.intake.clone();
+            tokio::spawn(async move {
+                while let Some((req, ack)) = rx.recv().await {
+                    let (network, request_id) = (req.network.clone(), req.request_id);
+                    let result = intake.accept(req, None);
+                    match &result {
+                        Ok(_) => info!("queue request {} of network {} from chain", request_id, network),
+                        Err(e) => info!("skip request {} of network {} from chain: {}", request_id, network, e.message()),
+                    }
+                    // the watcher moves its checkpoint past the request once it is persisted
+                    let _ = ack.send(result);
                 }
             });
         }
@@ -172,70 +421,110 @@ impl AizelInference {
         let response = client
             .upload_output(UploadOutputRequest { output, report })
             .await
-            .unwrap();
-        let mut resp: crate::node::aizel::UploadOutputResponse = response.into_inner();
-        let output_hash = hex::decode(resp.output_hash.split_off(2))
-            .unwrap()
-            .try_into()
-            .unwrap();
-        let report_hash = hex::decode(resp.report_hash.split_off(2))
-            .unwrap()
-            .try_into()
-            .unwrap();
+            .map_err(|e| Error::InferenceError {
+                message: format!("failed to upload output to gate server: {}", e.message()),
+            })?;
+        let resp: crate::node::aizel::UploadOutputResponse = response.into_inner();
+        let output_hash = AizelInference::decode_hash(&resp.output_hash)?;
+        let report_hash = AizelInference::decode_hash(&resp.report_hash)?;
         Ok((output_hash, report_hash))
     }
 
-    async fn handle_error(req: &InferenceRequest, e: Error, agent: &AttestationAgent) {
+    /// Decodes a 0x prefixed hash returned by the gate server.
+    fn decode_hash(hash: &str) -> Result<Hash, Error> {
+        let invalid = |message: String| Error::InvalidArgumentError {
+            argument: hash.to_string(),
+            message,
+        };
+        hex::decode(hash.trim_start_matches("0x"))
+            .map_err(|e| invalid(format!("failed to decode hex string {}", e)))?
+            .try_into()
+            .map_err(|_| invalid("hash is not 32 bytes long".to_string()))
+    }
+
+    async fn notify_error(stream: &Option<ChunkSender>, e: &Error) {
+        if let Some(stream) = stream {
+            let _ = stream.send(Err(Status::internal(e.to_string()))).await;
+        }
+    }
+
+    /// Completes the request unless its result may not make it on chain, it is replayed on restart then.
+    fn settle(log: &RequestLog, request_id: u64, status: TxStatus) {
+        match status {
+            TxStatus::Confirmed | TxStatus::Reverted => AizelInference::complete(log, request_id),
+            TxStatus::Replaced | TxStatus::Dropped | TxStatus::Pending => {}
+        }
+    }
+
+    fn complete(log: &RequestLog, request_id: u64) {
+        if let Err(e) = log.complete(request_id) {
+            error!("failed to mark request {} as completed: {}", request_id, e.to_string());
+        }
+    }
+
+    /// Submits the error of the request and fails it, unless the transaction may still be mined. If it
+    /// can't be submitted the request stays in the log and is replayed on restart.
+    async fn handle_error(req: &InferenceRequest, secret: &Secret, e: Error, agent: &AttestationAgent, registry: &RequestRegistry, log: &RequestLog) {
         let output = e.to_string();
-        let encrypted_output: String = match AizelInference::encrypt(&output, &req.user_pk) {
-            Ok(s) => s,
-            Err(_) => {
-                error!("failed to encrypt error msg");
-                return;
+        registry.transition(&req.network, req.request_id, RequestState::Submitting);
+        match AizelInference::submit_error(req, secret, &output, agent).await {
+            Ok(outcome) => {
+                registry.set_tx(&req.network, req.request_id, format!("{:?}", outcome.tx_hash), outcome.status.to_string());
+                if outcome.status != TxStatus::Pending {
+                    registry.fail(&req.network, req.request_id, output);
+                }
+                AizelInference::settle(log, req.request_id, outcome.status);
             }
-        };
+            Err(submit_error) => {
+                error!("failed to submit the error of request {}: {}", req.request_id, submit_error.to_string());
+                registry.fail(&req.network, req.request_id, output);
+            }
+        }
+    }
+
+    async fn submit_error(req: &InferenceRequest, secret: &Secret, output: &str, agent: &AttestationAgent) -> Result<TxOutcome, Error> {
+        let encrypted_output: String = AizelInference::encrypt(output, &req.user_pk)?;
         let output_hash: Digest = AizelInference::hash(&encrypted_output);
-        let report = if AIZEL_CONFIG.within_tee {
-            agent
-                .get_attestation_report(output_hash.to_string())
-                .await
-                .unwrap()
+        let report = if attestation_enabled() {
+            // the input may not have been read, it is left out of the report
+            let report_data = AizelInference::report_data(secret, req, &Digest::default(), &output_hash);
+            agent.get_attestation_report(report_data).await?
         } else {
             "mock report".to_string()
         };
 
         let report_hash: Digest = AizelInference::hash(&report);
-        let _ = AizelInference::submit_output(encrypted_output, report).await;
-        let _ = Contract::submit_inference(req.request_id, output_hash.0, report_hash.0, &req.network).await;
+        AizelInference::submit_output(encrypted_output, report).await?;
+        Contract::submit_inference(req.request_id, output_hash.0, report_hash.0, &req.network).await
     }
 
     async fn process_inference(
         req: &InferenceRequest,
         secret: Secret,
         agent: &AttestationAgent,
-        model_info: &ModelInfo
+        backend: &dyn ModelBackend,
+        registry: &RequestRegistry,
+        stream: &Option<ChunkSender>,
     ) -> Result<InferenceOutput, Error> {
+        registry.transition(&req.network, req.request_id, RequestState::Decrypting);
         let client: std::sync::Arc<MinioClient> = MinioClient::get_public_client().await;
         let user_input = client.get_inputs(INPUT_BUCKET, &req.input).await?;
+        let input_hash: Digest = AizelInference::hash(&user_input.input);
         let decrypted_input = AizelInference::decrypt(&secret, &user_input.input)?;
 
-        let output = if req.req_type == aizel::InferenceType::AizelModel as i32 {
-            MlClient::request(decrypted_input, &model_info.network).await?
-        } else {
-            if req.model_id == TRANSFER_AGENT_ID {
-                let from = pubkey_to_address(&req.user_pk).unwrap();
-                TransferAgentClient::transfer(req.request_id, decrypted_input, from, &req.network).await?
-            } else {
-                ChatClient::request(decrypted_input, &req.network).await?
-            }
+        registry.transition(&req.network, req.request_id, RequestState::Running);
+
+        let output = match stream {
+            Some(stream) => AizelInference::stream_output(decrypted_input, req, backend, stream).await?,
+            None => backend.infer(req, decrypted_input, None).await?,
         };
 
         let encrypted_output: String = AizelInference::encrypt(&output, &req.user_pk)?;
         let output_hash: Digest = AizelInference::hash(&encrypted_output);
         // upload the report to minio bucket
-        let report = if AIZEL_CONFIG.within_tee {
+        let report = if attestation_enabled() {
             agent
-                .get_attestation_report(output_hash.to_string())
+                .get_attestation_report(AizelInference::report_data(&secret, req, &input_hash, &output_hash))
                 .await?
         } else {
             "mock report".to_string()
@@ -247,7 +536,40 @@ impl AizelInference {
         })
     }
 
-    fn hash(message: &str) -> Digest {
+    /// Runs the request on `backend` and forwards every output delta, encrypted to the user public key, to `stream`.
+    async fn stream_output(input: String, req: &InferenceRequest, backend: &dyn ModelBackend, stream: &ChunkSender) -> Result<String, Error> {
+        let (delta_tx, mut delta_rx) = channel::<String>(DEFAULT_CHANNEL_SIZE);
+        let user_pk = req.user_pk.clone();
+        let stream = stream.clone();
+        let forwarder = tokio::spawn(async move {
+            while let Some(delta) = delta_rx.recv().await {
+                match AizelInference::encrypt(&delta, &user_pk) {
+                    Ok(output) => {
+                        let _ = stream.send(Ok(InferenceChunk { output, finished: false })).await;
+                    }
+                    Err(e) => error!("failed to encrypt output chunk {}", e.to_string()),
+                }
+            }
+        });
+        let output = backend.infer(req, input, Some(delta_tx)).await;
+        // make sure every delta is forwarded before the final chunk is sent
+        let _ = forwarder.await;
+        output
+    }
+
+    fn report_data(secret: &Secret, req: &InferenceRequest, input_hash: &Digest, output_hash: &Digest) -> ReportData {
+        ReportData {
+            node_pk: secret.name.0.to_vec(),
+            network: req.network.clone(),
+            request_id: req.request_id,
+            model_id: req.model_id,
+            input_hash: input_hash.0,
+            output_hash: output_hash.0,
+        }
+    }
+
+    /// keccak256 of the abi packed string, the hash outputs and reports are submitted under.
+    pub fn hash(message: &str) -> Digest {
         let token_hash = abi::encode_packed(&[Token::String(message.to_string())]).unwrap();
         Digest(utils::keccak256(token_hash))
     }
@@ -274,7 +596,7 @@ impl AizelInference {
         let ct = elgamal
             .encrypt(
                 plaintext.as_bytes(),
-                &PublicKey::from_slice(&hex::decode(user_pk).unwrap()).unwrap(),
+                &parse_pubkey(user_pk)?,
             )
             .map_err(|e| Error::InferenceError {
                 message: format!("failed to encrypt input {}", e.to_string()),
@@ -304,3 +626,11 @@ async fn test_openai_client() {
     println!("Content: {:?}", result.choices[0].message.content);
     println!("Response Headers: {:?}", result.headers);
 }
+
+#[test]
+fn test_decode_hash() {
+    let hash = format!("0x{}", "ab".repeat(32));
+    assert_eq!(AizelInference::decode_hash(&hash).unwrap(), [0xab; 32]);
+    assert!(AizelInference::decode_hash("0xabcd").is_err());
+    assert!(AizelInference::decode_hash("0xzz").is_err());
+}
@@ -0,0 +1,194 @@
+use super::aizel::{InferenceRequest, InferenceType};
+use super::config::{llama_slot_port, model_ram_budget, resident_models, RemoteBackendConfig, AIZEL_CONFIG};
+use super::model_client::ChatClient;
+use super::model_server::{LlamaServer, MlServer};
+use super::residency::{ModelPool, Slot};
+use crate::chains::contract::ModelInfo;
+use common::error::Error;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::RwLock;
+use tonic::async_trait;
+
+pub const LLAMA_BACKEND: &str = "llama";
+pub const ML_BACKEND: &str = "ml";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Health {
+    Up,
+    // the reason the backend stopped serving, e.g. the exit status of its process
+    Down(String),
+}
+
+/// A way of serving models. Requests are routed to a backend by `select_backend`, so adding a
+/// backend only requires implementing this trait and registering it in `create_backends`.
+/// A backend serves one model at a time, a `ModelPool` of them keeps several resident.
+#[async_trait]
+pub trait ModelBackend: Send + Sync {
+    /// Model the backend is serving, if any.
+    fn model(&self) -> Option<&ModelInfo>;
+    /// Fetches what the backend needs locally to serve `model_info`.
+    async fn prepare(&self, model_info: &ModelInfo) -> Result<(), Error>;
+    /// Memory `model_info` takes once started, counted against the memory budget of the network.
+    fn model_size(&self, _model_info: &ModelInfo) -> u64 {
+        0
+    }
+    /// Starts serving `model_info` in place of the current model. Also used to restart a backend
+    /// that went down, with its current model.
+    async fn start(&mut self, model_info: &ModelInfo) -> Result<(), Error>;
+    fn health(&mut self) -> Health;
+    /// Runs the request on the served model, forwarding output deltas to `deltas` if the backend
+    /// supports streaming. Returns the whole output.
+    async fn infer(&self, req: &InferenceRequest, input: String, deltas: Option<Sender<String>>) -> Result<String, Error>;
+    async fn stop(&mut self) -> Result<(), Error>;
+}
+
+pub type Backends = HashMap<String, ModelPool>;
+
+/// Name of the backend serving the request: an endpoint configured for the model, otherwise the
+/// local server for the format of the model recorded on chain, or for the inference type of the
+/// request if the format is unknown.
+pub fn select_backend<'a>(req: &InferenceRequest, model_info: &ModelInfo, remotes: &'a [RemoteBackendConfig]) -> &'a str {
+    if let Some(remote) = remotes
+        .iter()
+        .find(|r| r.network == req.network && r.models.contains(&req.model_id))
+    {
+        return &remote.name;
+    }
+    if let Some(backend) = model_info.backend_hint() {
+        return backend;
+    }
+    if req.req_type == InferenceType::AizelModel as i32 {
+        ML_BACKEND
+    } else {
+        LLAMA_BACKEND
+    }
+}
+
+pub fn remote_backends() -> &'static [RemoteBackendConfig] {
+    AIZEL_CONFIG.backends.as_deref().unwrap_or_default()
+}
+
+pub fn create_backends(network: &str) -> Result<Backends, Error> {
+    let mut backends: Backends = HashMap::new();
+    let mut llama_slots: Vec<Slot> = vec![];
+    for slot in 0..resident_models() {
+        llama_slots.push(Box::new(RwLock::new(LlamaServer::new(llama_slot_port(network, slot)?))));
+    }
+    backends.insert(LLAMA_BACKEND.to_string(), ModelPool::new(llama_slots, model_ram_budget()));
+    backends.insert(ML_BACKEND.to_string(), ModelPool::new(vec![Box::new(RwLock::new(MlServer::new()))], None));
+    for remote in remote_backends().iter().filter(|r| r.network == network) {
+        backends.insert(
+            remote.name.clone(),
+            ModelPool::new(vec![Box::new(RwLock::new(RemoteServer::new(remote.clone())))], None),
+        );
+    }
+    Ok(backends)
+}
+
+/// Any OpenAI compatible chat completion endpoint (vLLM, Ollama, ...) that serves the model itself.
+pub struct RemoteServer {
+    config: RemoteBackendConfig,
+    model_info: Option<ModelInfo>,
+}
+
+impl RemoteServer {
+    pub fn new(config: RemoteBackendConfig) -> Self {
+        Self {
+            config,
+            model_info: None,
+        }
+    }
+
+    fn client(&self) -> Result<ChatClient, Error> {
+        let model_info = self.model_info.as_ref().ok_or(Error::InferenceError {
+            message: format!("no model started on backend {}", self.config.name),
+        })?;
+        Ok(ChatClient {
+            endpoint: self.config.url.clone(),
+            model: self.config.model.clone().unwrap_or(model_info.name.clone()),
+            api_key: self.config.api_key.clone().unwrap_or_default(),
+        })
+    }
+}
+
+#[async_trait]
+impl ModelBackend for RemoteServer {
+    fn model(&self) -> Option<&ModelInfo> {
+        self.model_info.as_ref()
+    }
+
+    async fn prepare(&self, _model_info: &ModelInfo) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn start(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
+        self.model_info = Some(model_info.clone());
+        Ok(())
+    }
+
+    fn health(&mut self) -> Health {
+        Health::Up
+    }
+
+    async fn infer(&self, _req: &InferenceRequest, input: String, deltas: Option<Sender<String>>) -> Result<String, Error> {
+        let client = self.client()?;
+        match deltas {
+            Some(deltas) => client.stream_request(input, deltas).await,
+            None => client.request(input).await,
+        }
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        self.model_info = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(model_id: u64, req_type: InferenceType, network: &str) -> InferenceRequest {
+        InferenceRequest {
+            request_id: 1,
+            model_id,
+            input: String::new(),
+            user_pk: String::new(),
+            req_type: req_type as i32,
+            network: network.to_string(),
+        }
+    }
+
+    fn model(name: &str) -> ModelInfo {
+        ModelInfo {
+            name: name.to_string(),
+            cid: String::new(),
+            id: 1,
+            network: "aizel".to_string(),
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_select_backend() {
+        let remotes = vec![RemoteBackendConfig {
+            name: "ollama".to_string(),
+            network: "aizel".to_string(),
+            url: "http://localhost:11434/v1".to_string(),
+            models: vec![5],
+            model: Some("llama3".to_string()),
+            api_key: None,
+        }];
+        let unknown = model("model");
+        assert_eq!(select_backend(&request(5, InferenceType::Llama, "aizel"), &unknown, &remotes), "ollama");
+        assert_eq!(select_backend(&request(5, InferenceType::Llama, "avax"), &unknown, &remotes), LLAMA_BACKEND);
+        assert_eq!(select_backend(&request(1, InferenceType::Llama, "aizel"), &unknown, &remotes), LLAMA_BACKEND);
+        assert_eq!(select_backend(&request(3, InferenceType::AizelModel, "aizel"), &unknown, &remotes), ML_BACKEND);
+        // the model format recorded on chain wins over the request type
+        let gguf = model("llama2_7b_chat.Q4_0.gguf-1.0");
+        assert_eq!(select_backend(&request(1, InferenceType::AizelModel, "aizel"), &gguf, &remotes), LLAMA_BACKEND);
+        let archive = model("face.tar.gz");
+        assert_eq!(select_backend(&request(1, InferenceType::Llama, "aizel"), &archive, &remotes), ML_BACKEND);
+    }
+}
@@ -6,6 +6,7 @@ use serde_derive::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::sync::OnceCell;
 
 pub const DEFAULT_BASE_PORT: u16 = 8080;
@@ -14,6 +15,7 @@ pub const NODE_KEY_FILENAME: &str = "node_key.json";
 pub const DEFAULT_ROOT_DIR: &str = "aizel";
 pub const DEFAULT_MODEL_DIR: &str = "models";
 pub const DEFAULT_LOG_DIR: &str = "logs";
+pub const DEFAULT_QUEUE_DIR: &str = "queue";
 pub const DEFAULT_AIZEL_CONFIG: &str = "aizel_config.yml";
 pub const DEFAULT_NETWORK_CONFIG: &str = "config.json";
 pub const ML_DIR: &str = "aizel-face-recognition";
@@ -27,9 +29,38 @@ pub const OUTPUT_BUCKET: &str = "users-output";
 pub const REPORT_BUCKET: &str = "inference-report";
 
 pub const DEFAULT_CHANNEL_SIZE: usize = 1_000;
+pub const DEFAULT_REGISTRY_CAPACITY: usize = 10_000;
+pub const DEFAULT_QUEUE_COMPACT_THRESHOLD: usize = 100;
+pub const DEFAULT_INFERENCE_WORKERS: usize = 1;
+pub const DEFAULT_SCHEDULE_MAX_WAIT_SECS: u64 = 120;
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1_000;
+pub const DEFAULT_RESIDENT_MODELS: usize = 1;
 
 pub const LLAMA_SERVER_PORT: u16 = 8888;
+// llama.cpp server, decodes the requests of the inference workers in parallel slots
+pub const LLAMA_SERVER_BIN: &str = "/usr/local/bin/llama-server";
+// context of each parallel slot, the default of llama_cpp.server
+pub const LLAMA_SLOT_CTX_SIZE: usize = 2048;
 pub const ML_SERVER_PORT: u16 = 9888;
+pub const LLAMA_SERVER_READY_ROUTE: &str = "v1/models";
+pub const LLAMA_SERVER_READY_TIMEOUT_SECS: u64 = 600;
+pub const ML_SERVER_READY_TIMEOUT_SECS: u64 = 120;
+pub const READY_PROBE_INTERVAL_MILLIS: u64 = 500;
+pub const LOG_TAIL_LINES: usize = 20;
+pub const SUPERVISOR_INTERVAL_MILLIS: u64 = 2_000;
+pub const RESTART_BACKOFF_INITIAL_SECS: u64 = 1;
+pub const RESTART_BACKOFF_MAX_SECS: u64 = 300;
+// failed restarts after which the model is unloaded, the next request loads it again
+pub const RESTART_MAX_ATTEMPTS: u32 = 5;
+
+pub const DEFAULT_BACKFILL_BATCH: u64 = 1_000;
+pub const WATCHER_RETRY_SECS: u64 = 5;
+pub const DEFAULT_TX_CONFIRMATIONS: u64 = 1;
+pub const DEFAULT_TX_STUCK_TIMEOUT_SECS: u64 = 60;
+pub const DEFAULT_TX_MAX_REBROADCASTS: u32 = 3;
+pub const DEFAULT_TX_GAS_BUMP_PERCENT: u64 = 20;
+pub const TX_POLL_INTERVAL_MILLIS: u64 = 2_000;
+pub const NONCE_SYNC_RETRIES: u32 = 3;
 
 pub const TRANSFER_AGENT_ID: u64 = 2;   
 lazy_static! {
@@ -84,7 +115,88 @@ pub struct AizelConfig {
     pub node_bio: String,
     pub initial_stake: u64,
     pub within_tee: bool,
+    // TEE producing the reports, detected from the cloud metadata servers if auto or not set
+    pub tee_provider: Option<TeeProvider>,
     pub node_secret: Option<String>,
+    // number of requests processed concurrently per network
+    pub inference_workers: Option<usize>,
+    // order in which queued requests are dispatched to the workers
+    pub scheduler: Option<SchedulerConfig>,
+    // OpenAI compatible endpoints serving some of the models instead of the local servers
+    pub backends: Option<Vec<RemoteBackendConfig>>,
+    // number of llama models kept loaded per network, each in its own server
+    pub resident_models: Option<usize>,
+    // memory the resident llama models of a network may use, unlimited if not set
+    pub model_ram_budget_mb: Option<u64>,
+    // pull the requests addressed to the node from the inference contract events, the watcher
+    // stops if the contracts don't implement InferenceRequested and getNodeId
+    pub chain_watcher: Option<ChainWatcherConfig>,
+    // check the requests pushed over gRPC against the inference contract, if not set only when the
+    // contracts of every network implement getRequest and getNodeId
+    pub validate_requests: Option<bool>,
+    // how the transactions of the node are followed until they are final
+    pub transactions: Option<TxConfig>,
+    // reject the models whose on chain CID isn't a sha256 digest of the file or whose size is
+    // unknown, off if not set as the model contract doesn't enforce a CID format
+    pub strict_model_integrity: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TxConfig {
+    // blocks on top of the one a transaction was mined in before it counts as final
+    pub confirmations: Option<u64>,
+    pub stuck_timeout_secs: Option<u64>,
+    pub max_rebroadcasts: Option<u32>,
+    // gas price increase of each re-broadcast, nodes require at least 10%
+    pub gas_bump_percent: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChainWatcherConfig {
+    pub enabled: bool,
+    // blocks per eth_getLogs request when catching up after downtime
+    pub backfill_batch: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TeeProvider {
+    Gcp,
+    AlicloudTdx,
+    SevSnp,
+    // reports signed with the node key, to run the node locally
+    Mock,
+    Auto,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulePolicy {
+    // strictly in arrival order
+    Fifo,
+    // requests for the models resident in the model pools first, a request waiting longer than max_wait_secs goes next
+    ModelAffinity,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SchedulerConfig {
+    pub policy: SchedulePolicy,
+    pub max_wait_secs: Option<u64>,
+    // requests queued per network before new ones are rejected
+    pub capacity: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteBackendConfig {
+    pub name: String,
+    pub network: String,
+    // base url including the version, e.g. http://localhost:11434/v1
+    pub url: String,
+    // on chain ids of the models routed to this endpoint
+    pub models: Vec<u64>,
+    // model name expected by the endpoint, defaults to the on chain model name
+    pub model: Option<String>,
+    pub api_key: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -95,9 +207,20 @@ pub struct NetworkConfig {
     #[serde(rename = "evm_chain_id")]
     pub chain_id: u64,
     pub rpc_url: String,
+    // websocket endpoint for event subscriptions, derived from rpc_url if not set
+    #[serde(default)]
+    pub ws_url: Option<String>,
     pub contracts: Vec<ContractConfig>
 }
 
+impl NetworkConfig {
+    pub fn ws_endpoint(&self) -> String {
+        self.ws_url.clone().unwrap_or_else(|| {
+            self.rpc_url.replacen("https://", "wss://", 1).replacen("http://", "ws://", 1)
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ContractConfig {
     #[serde(rename = "smart_contract_name")]
@@ -142,6 +265,22 @@ pub fn logs_dir(network: &str) -> PathBuf {
     root_dir().join(DEFAULT_LOG_DIR).join(network)
 }
 
+pub fn queue_dir() -> PathBuf {
+    root_dir().join(DEFAULT_QUEUE_DIR)
+}
+
+pub fn queue_log_path(network: &str) -> PathBuf {
+    queue_dir().join(format!("{}.log", network))
+}
+
+pub fn nonce_path(network: &str) -> PathBuf {
+    queue_dir().join(format!("{}.nonce", network))
+}
+
+pub fn checkpoint_path(network: &str) -> PathBuf {
+    queue_dir().join(format!("{}.checkpoint", network))
+}
+
 pub fn node_key_path() -> PathBuf {
     root_dir().join(NODE_KEY_FILENAME)
 }
@@ -192,6 +331,33 @@ pub fn llama_server_port(network: &str) -> Result<u16, Error> {
     Ok(LLAMA_SERVER_PORT + network_id)
 }
 
+/// Port of a resident llama server. The servers of all networks interleave between the llama and
+/// the ML server ports, the first one of a network keeps the llama server port.
+pub fn llama_slot_port(network: &str, slot: usize) -> Result<u16, Error> {
+    let network_id = AIZEL_CONFIG.networks.iter().position(|x| {
+        x == network
+    }).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?;
+    slot_port(network_id, AIZEL_CONFIG.networks.len(), slot).ok_or(Error::InvalidArgumentError {
+        argument: "resident_models".to_string(),
+        message: format!(
+            "{} resident models on {} networks don't fit in the ports from {} to {}",
+            slot + 1,
+            AIZEL_CONFIG.networks.len(),
+            LLAMA_SERVER_PORT,
+            ML_SERVER_PORT - 1
+        ),
+    })
+}
+
+fn slot_port(network_id: usize, networks: usize, slot: usize) -> Option<u16> {
+    let offset = slot * networks + network_id;
+    if offset < (ML_SERVER_PORT - LLAMA_SERVER_PORT) as usize {
+        Some(LLAMA_SERVER_PORT + offset as u16)
+    } else {
+        None
+    }
+}
+
 pub fn ml_server_port(network: &str) -> Result<u16, Error> {
     let network_id = AIZEL_CONFIG.networks.iter().position(|x| {
         x == network
@@ -199,6 +365,82 @@ pub fn ml_server_port(network: &str) -> Result<u16, Error> {
     Ok(ML_SERVER_PORT + network_id)
 }
 
+/// Whether outputs are attested, by a TEE or by the mock provider.
+pub fn attestation_enabled() -> bool {
+    AIZEL_CONFIG.within_tee || tee_provider() == TeeProvider::Mock
+}
+
+pub fn tee_provider() -> TeeProvider {
+    AIZEL_CONFIG.tee_provider.unwrap_or(TeeProvider::Auto)
+}
+
+pub fn inference_workers() -> usize {
+    AIZEL_CONFIG.inference_workers.unwrap_or(DEFAULT_INFERENCE_WORKERS).max(1)
+}
+
+pub fn resident_models() -> usize {
+    AIZEL_CONFIG.resident_models.unwrap_or(DEFAULT_RESIDENT_MODELS).max(1)
+}
+
+pub fn model_ram_budget() -> Option<u64> {
+    AIZEL_CONFIG.model_ram_budget_mb.map(|mb| mb * 1024 * 1024)
+}
+
+pub fn tx_confirmations() -> u64 {
+    AIZEL_CONFIG.transactions.as_ref().and_then(|t| t.confirmations).unwrap_or(DEFAULT_TX_CONFIRMATIONS).max(1)
+}
+
+pub fn tx_stuck_timeout_secs() -> u64 {
+    AIZEL_CONFIG.transactions.as_ref().and_then(|t| t.stuck_timeout_secs).unwrap_or(DEFAULT_TX_STUCK_TIMEOUT_SECS)
+}
+
+pub fn tx_max_rebroadcasts() -> u32 {
+    AIZEL_CONFIG.transactions.as_ref().and_then(|t| t.max_rebroadcasts).unwrap_or(DEFAULT_TX_MAX_REBROADCASTS)
+}
+
+pub fn tx_gas_bump_percent() -> u64 {
+    AIZEL_CONFIG.transactions.as_ref().and_then(|t| t.gas_bump_percent).unwrap_or(DEFAULT_TX_GAS_BUMP_PERCENT).max(10)
+}
+
+pub fn request_validation() -> Option<bool> {
+    AIZEL_CONFIG.validate_requests
+}
+
+pub fn strict_model_integrity() -> bool {
+    AIZEL_CONFIG.strict_model_integrity.unwrap_or(false)
+}
+
+pub fn chain_watcher_enabled() -> bool {
+    AIZEL_CONFIG.chain_watcher.as_ref().map(|c| c.enabled).unwrap_or(false)
+}
+
+pub fn backfill_batch() -> u64 {
+    AIZEL_CONFIG
+        .chain_watcher
+        .as_ref()
+        .and_then(|c| c.backfill_batch)
+        .unwrap_or(DEFAULT_BACKFILL_BATCH)
+        .max(1)
+}
+
+pub fn schedule_policy() -> SchedulePolicy {
+    AIZEL_CONFIG.scheduler.as_ref().map(|s| s.policy).unwrap_or(SchedulePolicy::ModelAffinity)
+}
+
+pub fn queue_capacity() -> usize {
+    AIZEL_CONFIG.scheduler.as_ref().and_then(|s| s.capacity).unwrap_or(DEFAULT_QUEUE_CAPACITY).max(1)
+}
+
+pub fn schedule_max_wait() -> Duration {
+    Duration::from_secs(
+        AIZEL_CONFIG
+            .scheduler
+            .as_ref()
+            .and_then(|s| s.max_wait_secs)
+            .unwrap_or(DEFAULT_SCHEDULE_MAX_WAIT_SECS),
+    )
+}
+
 pub async fn initialize_network_configs_by_network() -> Result<Vec<NetworkConfig>, Error> {
     let client = reqwest::Client::new();
     let res = client.get(format!("{}/{}", AIZEL_CONFIG.config_server_url, "api/v1/networks")).send().await.map_err(|e| {
@@ -224,6 +466,17 @@ pub async fn initialize_network_configs() -> Result<Vec<NetworkConfig>, Error> {
     }
 }
 
+#[test]
+fn test_slot_port() {
+    assert_eq!(slot_port(0, 2, 0), Some(LLAMA_SERVER_PORT));
+    assert_eq!(slot_port(1, 2, 0), Some(LLAMA_SERVER_PORT + 1));
+    assert_eq!(slot_port(0, 2, 1), Some(LLAMA_SERVER_PORT + 2));
+    assert_eq!(slot_port(1, 2, 1), Some(LLAMA_SERVER_PORT + 3));
+    assert_eq!(slot_port(1, 2, 499), Some(ML_SERVER_PORT - 1));
+    // would take the ML server port
+    assert_eq!(slot_port(0, 2, 500), None);
+}
+
 #[test]
 fn test_aizel_config() {
     println!(
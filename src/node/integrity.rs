@@ -0,0 +1,204 @@
+use super::config::{strict_model_integrity, MODEL_BUCKET};
+use crate::chains::contract::ModelInfo;
+use crate::s3_minio::client::MinioClient;
+use common::error::Error;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+lazy_static! {
+    // digests of the model files already verified, valid while their length and mtime don't change
+    static ref VERIFIED_MODELS: Mutex<HashMap<PathBuf, (u64, SystemTime, String)>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the sha256 digest the model CID commits to, in lowercase hex.
+/// The model contract stores the CID as a plain string (`Models.ModelDetails.CID`) without enforcing
+/// a format, it is also the key the model is downloaded under from the data node.
+/// Accepts `sha256:<hex>`, `0x<hex>` and bare hex; other CIDs, e.g. IPFS ones that hash a DAG
+/// rather than the file, can't be checked against the file.
+pub fn expected_digest(cid: &str) -> Option<String> {
+    let hex = cid
+        .strip_prefix("sha256:")
+        .or_else(|| cid.strip_prefix("0x"))
+        .unwrap_or(cid);
+    if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hex.to_lowercase())
+    } else {
+        None
+    }
+}
+
+fn integrity_error(model_info: &ModelInfo, message: String) -> Error {
+    Error::ModelIntegrityError {
+        model: format!("{} ({})", model_info.name, model_info.id),
+        message,
+    }
+}
+
+/// Checks the file at `path` against the size and the CID of the model, returns its sha256 digest.
+pub async fn verify_model(path: &Path, model_info: &ModelInfo) -> Result<String, Error> {
+    verify_model_with(path, model_info, strict_model_integrity()).await
+}
+
+/// In strict mode a model whose CID isn't a sha256 digest or whose size is unknown is rejected, it
+/// could be swapped unnoticed. Otherwise what can't be checked is skipped with a warning, the digest
+/// is still returned to be measured.
+async fn verify_model_with(path: &Path, model_info: &ModelInfo, strict: bool) -> Result<String, Error> {
+    let file_error = |e: std::io::Error| Error::FileError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    };
+    let expected = expected_digest(&model_info.cid);
+    if expected.is_none() {
+        let message = format!("CID {} is not a sha256 digest", model_info.cid);
+        if strict {
+            return Err(integrity_error(model_info, message));
+        }
+        warn!("digest of model {} not checked: {}", model_info.id, message);
+    }
+    let metadata = fs::metadata(path).map_err(file_error)?;
+    if model_info.size == 0 && !strict {
+        warn!("size of model {} not checked: unknown on chain", model_info.id);
+    } else if metadata.len() != model_info.size {
+        return Err(integrity_error(
+            model_info,
+            format!("size is {} bytes, expect {}", metadata.len(), model_info.size),
+        ));
+    }
+    let modified = metadata.modified().map_err(file_error)?;
+    let cached = VERIFIED_MODELS
+        .lock()
+        .unwrap()
+        .get(path)
+        .filter(|(len, mtime, _)| *len == metadata.len() && *mtime == modified)
+        .map(|(_, _, digest)| digest.clone());
+    let digest = match cached {
+        Some(digest) => digest,
+        None => {
+            let file = path.to_path_buf();
+            tokio::task::spawn_blocking(move || sha256::try_digest(file.as_path()))
+                .await
+                .map_err(|e| integrity_error(model_info, format!("failed to hash model {}", e)))?
+                .map_err(file_error)?
+        }
+    };
+    if let Some(expected) = expected.filter(|expected| *expected != digest) {
+        return Err(integrity_error(
+            model_info,
+            format!("digest is {}, expect {}", digest, expected),
+        ));
+    }
+    VERIFIED_MODELS
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (metadata.len(), modified, digest.clone()));
+    Ok(digest)
+}
+
+/// Makes sure a verified copy of the model is at `path`, downloading it from the data node if
+/// the file is missing or doesn't match. The download goes to a temporary file that is renamed
+/// once verified, so a crash never leaves a partial model behind `path`.
+/// Returns the digest of the model and whether it was downloaded.
+pub async fn fetch_model(model_info: &ModelInfo, path: &Path) -> Result<(String, bool), Error> {
+    if path.exists() {
+        match verify_model(path, model_info).await {
+            Ok(digest) => return Ok((digest, false)),
+            Err(e) => {
+                warn!("download model {} again: {}", model_info.id, e.to_string());
+                fs::remove_file(path).map_err(|e| Error::FileError {
+                    path: path.to_path_buf(),
+                    message: e.to_string(),
+                })?;
+            }
+        }
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".part");
+    let tmp_path = PathBuf::from(tmp_path);
+    let _ = fs::remove_file(&tmp_path);
+
+    let client = MinioClient::get_data_client(&model_info.network).await;
+    client
+        .download_model(MODEL_BUCKET, &model_info.cid, &tmp_path)
+        .await?;
+    let digest = match verify_model(&tmp_path, model_info).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+    fs::rename(&tmp_path, path).map_err(|e| Error::FileError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    // the rename keeps the mtime, so the digest stays cached for the final path
+    let cached = VERIFIED_MODELS.lock().unwrap().remove(&tmp_path);
+    if let Some(entry) = cached {
+        VERIFIED_MODELS.lock().unwrap().insert(path.to_path_buf(), entry);
+    }
+    info!("model {} verified, sha256 {}", model_info.id, digest);
+    Ok((digest, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_DIGEST: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn model(cid: &str, size: u64) -> ModelInfo {
+        ModelInfo {
+            name: "model".to_string(),
+            cid: cid.to_string(),
+            id: 1,
+            network: "aizel".to_string(),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_expected_digest() {
+        assert_eq!(expected_digest(HELLO_DIGEST).unwrap(), HELLO_DIGEST);
+        assert_eq!(
+            expected_digest(&format!("sha256:{}", HELLO_DIGEST.to_uppercase())).unwrap(),
+            HELLO_DIGEST
+        );
+        assert_eq!(expected_digest(&format!("0x{}", HELLO_DIGEST)).unwrap(), HELLO_DIGEST);
+        assert!(expected_digest("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_model() {
+        let path = std::env::temp_dir().join(format!("aizel_verify_model_{}", std::process::id()));
+        fs::write(&path, "hello").unwrap();
+        let ipfs = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+        for strict in [true, false] {
+            assert_eq!(verify_model_with(&path, &model(HELLO_DIGEST, 5), strict).await.unwrap(), HELLO_DIGEST);
+            assert!(matches!(
+                verify_model_with(&path, &model(HELLO_DIGEST, 4), strict).await,
+                Err(Error::ModelIntegrityError { .. })
+            ));
+            assert!(matches!(
+                verify_model_with(&path, &model(&"0".repeat(64), 5), strict).await,
+                Err(Error::ModelIntegrityError { .. })
+            ));
+        }
+        // in strict mode neither an unknown size nor a CID that isn't a digest is trusted
+        assert!(matches!(
+            verify_model_with(&path, &model(HELLO_DIGEST, 0), true).await,
+            Err(Error::ModelIntegrityError { .. })
+        ));
+        assert!(matches!(
+            verify_model_with(&path, &model(ipfs, 5), true).await,
+            Err(Error::ModelIntegrityError { .. })
+        ));
+        assert_eq!(verify_model_with(&path, &model(HELLO_DIGEST, 0), false).await.unwrap(), HELLO_DIGEST);
+        assert_eq!(verify_model_with(&path, &model(ipfs, 5), false).await.unwrap(), HELLO_DIGEST);
+        fs::remove_file(&path).unwrap();
+    }
+}
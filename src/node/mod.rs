@@ -1,6 +1,15 @@
 pub mod aizel;
 pub mod aizel_server;
+pub mod backend;
 pub mod config;
+pub mod integrity;
 pub mod model_client;
 pub mod model_server;
 pub mod node;
+pub mod readiness;
+pub mod request_log;
+pub mod request_registry;
+pub mod residency;
+pub mod scheduler;
+pub mod supervisor;
+pub mod validation;
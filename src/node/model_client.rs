@@ -1,4 +1,4 @@
-use super::config::{llama_server_port, ml_server_port, COIN_ADDRESS_MAPPING};
+use super::config::{ml_server_port, COIN_ADDRESS_MAPPING};
 use crate::chains::contract::Contract;
 use common::error::Error;
 use ethers::core::utils::{parse_units, ParseUnits};
@@ -8,19 +8,30 @@ use openai_api_rs::v1::api::OpenAIClient;
 use openai_api_rs::v1::chat_completion::{self, ChatCompletionRequest};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tonic::async_trait;
+use tokio::sync::mpsc::Sender;
 
-#[async_trait]
-pub trait ModelClient: Send + Sync {
-    async fn request(input: String) -> Result<String, Error>;
+/// Client of an OpenAI compatible chat completion endpoint.
+pub struct ChatClient {
+    // base url including the version, e.g. http://localhost:8888/v1
+    pub endpoint: String,
+    pub model: String,
+    pub api_key: String,
 }
-pub struct ChatClient {}
 
 impl ChatClient {
-    pub async fn request(input: String, network: &str) -> Result<String, Error> {
-        let client = OpenAIClient::new_with_endpoint(format!("http://localhost:{}/v1", llama_server_port(network)?), String::new());
+    /// Client of a local llama server.
+    pub fn local(port: u16) -> Self {
+        Self {
+            endpoint: format!("http://localhost:{}/v1", port),
+            model: String::new(),
+            api_key: String::new(),
+        }
+    }
+
+    pub async fn request(&self, input: String) -> Result<String, Error> {
+        let client = OpenAIClient::new_with_endpoint(self.endpoint.clone(), self.api_key.clone());
         let req = ChatCompletionRequest::new(
-            String::new(),
+            self.model.clone(),
             vec![chat_completion::ChatCompletionMessage {
                 role: chat_completion::MessageRole::user,
                 content: chat_completion::Content::Text(input),
@@ -33,17 +44,112 @@ impl ChatClient {
             .chat_completion(req)
             .await
             .map_err(|e| Error::InferenceError {
-                message: format!("failed to request local llama sevrer {}", e.to_string()),
+                message: format!("failed to request {} {}", self.endpoint, e.to_string()),
             })?;
         match &result.choices[0].message.content {
             Some(c) => Ok(c.clone()),
             None => {
                 return Err(Error::InferenceError {
-                    message: "response is empty from local llama server".to_string(),
+                    message: format!("response is empty from {}", self.endpoint),
                 });
             }
         }
     }
+
+    /// Requests a chat completion with `stream: true` and forwards every token delta to `deltas`
+    /// as soon as the server emits it. Returns the concatenated output.
+    pub async fn stream_request(&self, input: String, deltas: Sender<String>) -> Result<String, Error> {
+        let client = reqwest::Client::new();
+        let body = serde_json::json!({
+            "model": self.model,
+            "messages": [{"role": "user", "content": input}],
+            "stream": true,
+        });
+        let mut req = client
+            .post(format!("{}/chat/completions", self.endpoint))
+            .json(&body);
+        if !self.api_key.is_empty() {
+            req = req.bearer_auth(&self.api_key);
+        }
+        let mut res = req
+            .send()
+            .await
+            .map_err(|e| Error::InferenceError {
+                message: format!("failed to request {} {}", self.endpoint, e.to_string()),
+            })?;
+        if !res.status().is_success() {
+            return Err(Error::InferenceError {
+                message: format!("{} returned status {}", self.endpoint, res.status()),
+            });
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut output = String::new();
+        while let Some(bytes) = res.chunk().await.map_err(|e| Error::InferenceError {
+            message: format!("failed to read stream from {} {}", self.endpoint, e.to_string()),
+        })? {
+            buffer.extend_from_slice(&bytes);
+            // server sent events are separated by new lines, a line may be split across chunks
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                match parse_stream_line(&String::from_utf8_lossy(&line))? {
+                    StreamEvent::Delta(content) => {
+                        output.push_str(&content);
+                        // the caller may have gone away, the output is still needed for submission
+                        let _ = deltas.send(content).await;
+                    }
+                    StreamEvent::Done => return Ok(output),
+                    StreamEvent::Skip => {}
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionChunk {
+    choices: Vec<ChatCompletionChunkChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionChunkChoice {
+    delta: ChatCompletionChunkDelta,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionChunkDelta {
+    content: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum StreamEvent {
+    Delta(String),
+    Done,
+    Skip,
+}
+
+fn parse_stream_line(line: &str) -> Result<StreamEvent, Error> {
+    let data = match line.trim().strip_prefix("data:") {
+        Some(data) => data.trim(),
+        None => return Ok(StreamEvent::Skip),
+    };
+    if data == "[DONE]" {
+        return Ok(StreamEvent::Done);
+    }
+    let chunk: ChatCompletionChunk = serde_json::from_str(data).map_err(|e| Error::SerDeError {
+        message: format!("failed to parse stream chunk {}", e.to_string()),
+    })?;
+    let content: String = chunk
+        .choices
+        .into_iter()
+        .filter_map(|c| c.delta.content)
+        .collect();
+    if content.is_empty() {
+        Ok(StreamEvent::Skip)
+    } else {
+        Ok(StreamEvent::Delta(content))
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -56,8 +162,8 @@ pub struct TransferInfo {
 pub struct TransferAgentClient {}
 
 impl TransferAgentClient {
-    pub async fn transfer(request_id: u64, input: String, from: String, network: &str) -> Result<String, Error> {
-        let transfer_info = TransferAgentClient::request(input, network).await?;
+    pub async fn transfer(request_id: u64, input: String, from: String, network: &str, port: u16) -> Result<String, Error> {
+        let transfer_info = TransferAgentClient::request(input, port).await?;
         let token_address = COIN_ADDRESS_MAPPING.get(network).ok_or(Error::NetworkConfigNotFoundError { network: network.to_string() })?.get(&transfer_info.token).ok_or(Error::InferenceError { message: format!("failed to transfer, token {} is unkown", &transfer_info.token) })?;
         let pu: ParseUnits = parse_units(transfer_info.amount, 18).unwrap();
         let amount = U256::from(pu);
@@ -81,7 +187,7 @@ impl TransferAgentClient {
         Ok(output)
     }
 
-    pub async fn request(input: String, network: &str) -> Result<TransferInfo, Error> {
+    pub async fn request(input: String, port: u16) -> Result<TransferInfo, Error> {
         let mut properties = HashMap::new();
         properties.insert(
             "to".to_string(),
@@ -107,7 +213,7 @@ impl TransferAgentClient {
                 ..Default::default()
             }),
         );
-        let client = OpenAIClient::new_with_endpoint(format!("http://localhost:{}/v1", llama_server_port(network)?), String::new());
+        let client = OpenAIClient::new_with_endpoint(format!("http://localhost:{}/v1", port), String::new());
         let req = ChatCompletionRequest::new(
             String::new(),
             vec![chat_completion::ChatCompletionMessage {
@@ -208,8 +314,24 @@ async fn request_ml_model() {
 
 #[tokio::test] 
 async fn test_chat_client() {
-    println!("{:?}", ChatClient::request("what's bit coin?".to_string(), "aizel").await);
-    println!("{:?}", ChatClient::request("what's bit coin?".to_string(), "peaq").await);
+    use super::config::llama_server_port;
+    println!("{:?}", ChatClient::local(llama_server_port("aizel").unwrap()).request("what's bit coin?".to_string()).await);
+    println!("{:?}", ChatClient::local(llama_server_port("peaq").unwrap()).request("what's bit coin?".to_string()).await);
+}
+
+#[test]
+fn test_parse_stream_line() {
+    assert_eq!(parse_stream_line(": ping").unwrap(), StreamEvent::Skip);
+    assert_eq!(parse_stream_line("data: [DONE]\n").unwrap(), StreamEvent::Done);
+    assert_eq!(
+        parse_stream_line("data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Bit\"}}]}\n").unwrap(),
+        StreamEvent::Delta("Bit".to_string())
+    );
+    assert_eq!(
+        parse_stream_line("data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}").unwrap(),
+        StreamEvent::Skip
+    );
+    assert!(parse_stream_line("data: {").is_err());
 }
 
 #[tokio::test]
@@ -1,118 +1,195 @@
-use super::config::{llama_server_port, logs_dir, ml_model_config, ml_model_config_with_id, ml_models_dir, ml_models_start_script, ml_server_port, models_dir, source_ml_models_dir, MODEL_BUCKET, TRANSFER_AGENT_ID};
+use super::aizel::InferenceRequest;
+use super::backend::{Health, ModelBackend};
+use super::config::{inference_workers, logs_dir, ml_model_config, ml_model_config_with_id, ml_models_dir, ml_models_start_script, ml_server_port, models_dir, source_ml_models_dir, LLAMA_SERVER_BIN, LLAMA_SERVER_READY_ROUTE, LLAMA_SERVER_READY_TIMEOUT_SECS, LLAMA_SLOT_CTX_SIZE, ML_SERVER_READY_TIMEOUT_SECS, TRANSFER_AGENT_ID};
+use super::model_client::{ChatClient, MlClient, TransferAgentClient};
+use super::integrity::{fetch_model, verify_model};
+use super::readiness::{Readiness, ReadinessProbe};
 use crate::chains::contract::ModelInfo;
-use crate::s3_minio::client::MinioClient;
+use crate::chains::ethereum::pubkey_to_address;
+use crate::tee::measurement::measure_model;
 use common::error::Error;
 use log::{error, info};
 use std::fs;
-use std::process::{Child, Command, Stdio};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::Sender;
 use tonic::async_trait;
 use flate2::read::GzDecoder;
 use tar::Archive;
 
-#[async_trait]
-pub trait ModelServer: Send + Sync {
-    async fn run_server(model_info: &ModelInfo) -> Result<(), Error>;
+async fn stop_child(child: &mut Option<Child>, server: &str) -> Result<(), Error> {
+    if let Some(mut c) = child.take() {
+        // kills the process and reaps it without blocking the runtime
+        if let Err(e) = c.kill().await {
+            error!("failed to kill {} {}", server, e.to_string());
+            *child = Some(c);
+            return Err(Error::InferenceError {
+                message: format!("failed to stop {}", server),
+            });
+        }
+    }
+    Ok(())
 }
 
+fn child_health(child: &mut Option<Child>) -> Health {
+    match child.as_mut().map(|c| c.try_wait()) {
+        Some(Ok(Some(status))) => Health::Down(status.to_string()),
+        Some(Err(e)) => Health::Down(e.to_string()),
+        _ => Health::Up,
+    }
+}
+
+/// Serves chat models with a llama-server process listening on `port`, the transfer agent with llama_cpp.server.
 pub struct LlamaServer {
-    pub child: Child,
-    pub current_model: u64,
+    port: u16,
+    child: Option<Child>,
+    model_info: Option<ModelInfo>,
 }
 
 impl LlamaServer {
-    async fn prepare_model(model_info: &ModelInfo) -> Result<(), Error> {
-        let network = &model_info.network;
-        let model_path = models_dir(network).join(&model_info.name);
-    
-        if model_path.exists() {
-            return Ok(());
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            child: None,
+            model_info: None,
         }
-        let client = MinioClient::get_data_client(network).await;
-        client
-            .download_model(
-                MODEL_BUCKET,
-                &model_info.cid,
-                &models_dir(network).join(&model_info.name),
-            )
-            .await?;
+    }
+
+    async fn prepare_model(model_info: &ModelInfo) -> Result<(), Error> {
+        let model_path = models_dir(&model_info.network).join(&model_info.name);
+        fetch_model(model_info, &model_path).await?;
         Ok(())
     }
 
-    async fn run_llama_server(model_info: &ModelInfo) -> Result<Child, Error> {
-        LlamaServer::prepare_model(model_info).await?;
+    async fn run_llama_server(model_info: &ModelInfo, port: u16) -> Result<Child, Error> {
         let network = &model_info.network;
         
-        let llama_server_output = fs::File::create(logs_dir(network).join(format!("llama_stdout_{}.txt", model_info.network))).unwrap();
-        let llama_server_error = fs::File::create(logs_dir(network).join(format!("llama_stderr_{}.txt", model_info.network))).unwrap();
+        let llama_server_output = fs::File::create(logs_dir(network).join(format!("llama_stdout_{}_{}.txt", model_info.network, port))).unwrap();
+        let stderr_log = logs_dir(network).join(format!("llama_stderr_{}_{}.txt", model_info.network, port));
+        let llama_server_error = fs::File::create(&stderr_log).unwrap();
         let model_path = models_dir(network).join(&model_info.name);
         info!(
             "llama cpp server model path {}",
             model_path.to_str().unwrap()
         );
-        let mut command = Command::new("/python/bin/python3");
-        let port: u16 = llama_server_port(network)?;
-        let mut command = command
-            .arg("-m")
-            .arg("llama_cpp.server")
-            .arg("--model")
-            .arg(model_path.to_str().unwrap())
-            .arg("--seed")
-            .arg("-1")
-            .arg("--n_threads")
-            .arg("-1")
-            .arg("--n_threads_batch")
-            .arg("-1")
+        let mut command;
+        if model_info.id == TRANSFER_AGENT_ID {
+            // function calling is only supported by the python server, which runs one request at a time
+            command = Command::new("/python/bin/python3");
+            command
+                .arg("-m")
+                .arg("llama_cpp.server")
+                .arg("--model")
+                .arg(model_path.to_str().unwrap())
+                .arg("--seed")
+                .arg("-1")
+                .arg("--n_threads")
+                .arg("-1")
+                .arg("--n_threads_batch")
+                .arg("-1")
+                .arg("--chat_format")
+                .arg("chatml-function-calling");
+        } else {
+            // one slot per inference worker, each with the context a single request had before
+            let slots = inference_workers();
+            command = Command::new(LLAMA_SERVER_BIN);
+            command
+                .arg("--model")
+                .arg(model_path.to_str().unwrap())
+                .arg("--seed")
+                .arg("-1")
+                .arg("--parallel")
+                .arg(format!("{}", slots))
+                .arg("--ctx-size")
+                .arg(format!("{}", slots * LLAMA_SLOT_CTX_SIZE));
+        }
+        command
             .arg("--port")
             .arg::<String>(format!("{}", port))
             .stdout(Stdio::from(llama_server_output))
             .stderr(Stdio::from(llama_server_error));
         info!("run llama cpp server for network {} on port {}", network, port);
-        if model_info.id == TRANSFER_AGENT_ID {
-            command = command.arg("--chat_format").arg("chatml-function-calling");
+        let mut child = command.spawn().map_err(|e| Error::InferenceError {
+            message: format!("failed to start llama server {}", e.to_string()),
+        })?;
+        ReadinessProbe {
+            server: format!("llama server {} ({})", model_info.name, network),
+            readiness: Readiness::Http(format!("http://localhost:{}/{}", port, LLAMA_SERVER_READY_ROUTE)),
+            timeout: Duration::from_secs(LLAMA_SERVER_READY_TIMEOUT_SECS),
+            stderr_log,
         }
-        let child = command.spawn().expect("Failed to start Python script");
-        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        .wait(&mut child)
+        .await?;
         Ok(child)
     }
+}
 
-    pub async fn new(model_info: &ModelInfo) -> Result<Self, Error> {
-        let child = LlamaServer::run_llama_server(model_info).await?;
-        Ok(Self {
-            child,
-            current_model: model_info.id,
-        })
+#[async_trait]
+impl ModelBackend for LlamaServer {
+    fn model(&self) -> Option<&ModelInfo> {
+        self.model_info.as_ref()
     }
 
-    pub async fn run(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
-        let model_id = model_info.id;
-        if model_id == self.current_model {
-            return Ok(());
+    async fn prepare(&self, model_info: &ModelInfo) -> Result<(), Error> {
+        LlamaServer::prepare_model(model_info).await
+    }
+
+    fn model_size(&self, model_info: &ModelInfo) -> u64 {
+        // the weights are mapped into memory as a whole
+        fs::metadata(models_dir(&model_info.network).join(&model_info.name))
+            .map(|m| m.len())
+            .unwrap_or(0)
+    }
+
+    async fn start(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
+        if let Some(current) = &self.model_info {
+            info!("change model from {} to {} in network {}", current.id, model_info.id, model_info.network);
+        }
+        self.stop().await?;
+        // measure the weights right before they are served
+        let digest = verify_model(&models_dir(&model_info.network).join(&model_info.name), model_info).await?;
+        measure_model(model_info, &digest)?;
+        self.child = Some(LlamaServer::run_llama_server(model_info, self.port).await?);
+        self.model_info = Some(model_info.clone());
+        Ok(())
+    }
+
+    fn health(&mut self) -> Health {
+        child_health(&mut self.child)
+    }
+
+    async fn infer(&self, req: &InferenceRequest, input: String, deltas: Option<Sender<String>>) -> Result<String, Error> {
+        if req.model_id == TRANSFER_AGENT_ID {
+            let from = pubkey_to_address(&req.user_pk)?;
+            return TransferAgentClient::transfer(req.request_id, input, from, &req.network, self.port).await;
         }
-        info!("change model from {} to {} in network {}", self.current_model, model_id, model_info.network);
-        match self.child.kill() {
-            Ok(()) => {
-                let _ = self.child.wait();
-                let child = LlamaServer::run_llama_server(model_info).await?;
-                self.current_model = model_id;
-                self.child = child;
-            }
-            Err(e) => {
-                error!("failed to kill llama server {}", e.to_string());
-                return Err(Error::InferenceError {
-                    message: "failed to change model".to_string(),
-                });
-            }
+        let client = ChatClient::local(self.port);
+        match deltas {
+            Some(deltas) => client.stream_request(input, deltas).await,
+            None => client.request(input).await,
         }
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        stop_child(&mut self.child, "llama server").await?;
+        self.model_info = None;
         Ok(())
     }
 }
 
+/// Serves the face recognition models with the ML server on the ML port of the network.
+#[derive(Default)]
 pub struct MlServer {
-    pub child: Child,
-    pub current_model: u64,
+    child: Option<Child>,
+    model_info: Option<ModelInfo>,
 }
 
 impl MlServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     fn save_model_config(model_info: &ModelInfo) -> Result<(), Error> {
         fs::copy(ml_model_config(&model_info.network), ml_model_config_with_id(&model_info.network, model_info.id)).unwrap();
         Ok(())
@@ -131,24 +208,16 @@ impl MlServer {
             })?;
         }
         let model_path = ml_models_dir(network).join(&model_info.name);
-    
-        if model_path.exists() {
-            let _ = MlServer::recover_model_config(model_info);
-            return Ok(());
-        }
 
         // if !model_info.name.ends_with("tar.gz") {
         //     return Err(Error::InferenceError { message: format!("model format not supported {}", model_info.name) })
         // }
 
-        let client = MinioClient::get_data_client(&model_info.network).await;
-        client
-            .download_model(
-                MODEL_BUCKET,
-                &model_info.cid,
-                &model_path,
-            )
-            .await?;
+        let (_, downloaded) = fetch_model(model_info, &model_path).await?;
+        if !downloaded {
+            let _ = MlServer::recover_model_config(model_info);
+            return Ok(());
+        }
         
         let tar_gz = fs::File::open(model_path).unwrap();
         let tar = GzDecoder::new(tar_gz);
@@ -159,61 +228,66 @@ impl MlServer {
     }
 
     async fn run_ml_server(model_info: &ModelInfo) -> Result<Child, Error> {
-        MlServer::prepare_model(&model_info).await?;
         let network = &model_info.network;
         let ml_server_output = fs::File::create(logs_dir(network).join(format!("ml_stdout_{}.txt", model_info.network))).unwrap();
-        let ml_server_error = fs::File::create(logs_dir(network).join(format!("ml_stderr_{}.txt", model_info.network))).unwrap();
+        let stderr_log = logs_dir(network).join(format!("ml_stderr_{}.txt", model_info.network));
+        let ml_server_error = fs::File::create(&stderr_log).unwrap();
+        let port = ml_server_port(network)?;
         
         let mut command: Command = Command::new("bash");
         let command = command.arg(ml_models_start_script().to_str().unwrap())
-            .arg(format!("{}", ml_server_port(network)?))
+            .arg(format!("{}", port))
             .arg(ml_models_dir(network))
             .stdout(Stdio::from(ml_server_output))
             .stderr(Stdio::from(ml_server_error));
-        let child = command.spawn().expect("Failed to start Python script");
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        let mut child = command.spawn().map_err(|e| Error::InferenceError {
+            message: format!("failed to start ml server {}", e.to_string()),
+        })?;
+        ReadinessProbe {
+            server: format!("ml server {} ({})", model_info.name, network),
+            readiness: Readiness::Tcp(port),
+            timeout: Duration::from_secs(ML_SERVER_READY_TIMEOUT_SECS),
+            stderr_log,
+        }
+        .wait(&mut child)
+        .await?;
         Ok(child)
     }
+}
 
-    pub async fn new(model_info: &Option<ModelInfo>) -> Result<Self, Error> {
-        match model_info {
-            Some(m) => {
-                let child = MlServer::run_ml_server(m).await?;
-                Ok(Self {
-                    child,
-                    current_model: m.id,
-                })
-            }
-            None => {
-                let child = Command::new("sleep").arg("infinity").spawn().expect("Failed to run sleep command");
-                Ok(Self {
-                    child,
-                    current_model: 0,
-                })
-            }
-        }
+#[async_trait]
+impl ModelBackend for MlServer {
+    fn model(&self) -> Option<&ModelInfo> {
+        self.model_info.as_ref()
     }
 
-    pub async fn run(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
-        let model_id = model_info.id;
-        if model_id == self.current_model {
-            return Ok(());
-        }
-        info!("change model from {} to {}", self.current_model, model_id);
-        match self.child.kill() {
-            Ok(()) => {
-                let _ = self.child.wait();
-                let child = MlServer::run_ml_server(model_info).await?;
-                self.current_model = model_id;
-                self.child = child;
-            }
-            Err(e) => {
-                error!("failed to kill ml server {}", e.to_string());
-                return Err(Error::InferenceError {
-                    message: "failed to change model".to_string(),
-                });
-            }
+    async fn prepare(&self, model_info: &ModelInfo) -> Result<(), Error> {
+        MlServer::prepare_model(model_info).await
+    }
+
+    async fn start(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
+        if let Some(current) = &self.model_info {
+            info!("change model from {} to {}", current.id, model_info.id);
         }
+        self.stop().await?;
+        let digest = verify_model(&ml_models_dir(&model_info.network).join(&model_info.name), model_info).await?;
+        measure_model(model_info, &digest)?;
+        self.child = Some(MlServer::run_ml_server(model_info).await?);
+        self.model_info = Some(model_info.clone());
+        Ok(())
+    }
+
+    fn health(&mut self) -> Health {
+        child_health(&mut self.child)
+    }
+
+    async fn infer(&self, req: &InferenceRequest, input: String, _deltas: Option<Sender<String>>) -> Result<String, Error> {
+        MlClient::request(input, &req.network).await
+    }
+
+    async fn stop(&mut self) -> Result<(), Error> {
+        stop_child(&mut self.child, "ml server").await?;
+        self.model_info = None;
         Ok(())
     }
 }
@@ -1,21 +1,21 @@
 use super::aizel::inference_server::InferenceServer;
 use super::{
     aizel_server::AizelInference,
-    config::{models_dir, node_key_path, root_dir, AIZEL_CONFIG, initialize_network_configs, ml_dir},
+    config::{attestation_enabled, models_dir, node_key_path, root_dir, AIZEL_CONFIG, initialize_network_configs, ml_dir, queue_dir},
 };
-use crate::chains::contract::{Contract, NONCE_MANAGERS};
+use crate::chains::contract::Contract;
 use crate::node::config::{logs_dir, NETWORK_CONFIGS};
 use crate::{
     crypto::secret::{Export, Secret},
     tee::attestation::AttestationAgent,
 };
 use common::error::Error;
+use common::tee::report_data::ReportData;
 use log::{error, info};
 use std::fs;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use tonic::transport::Server;
-use ethers::{providers::{Http, Provider}, signers::{LocalWallet, Signer}, middleware::NonceManagerMiddleware};
 pub struct Node {
     pub address: SocketAddr,
     pub secret: Secret,
@@ -27,6 +27,7 @@ impl Node {
         NETWORK_CONFIGS.set(initialize_network_configs().await?).unwrap();
         assert_eq!(AIZEL_CONFIG.data_nodes.len(), AIZEL_CONFIG.networks.len());
         fs::create_dir_all(root_dir()).unwrap();
+        fs::create_dir_all(queue_dir()).unwrap();
         AIZEL_CONFIG.networks.iter().for_each(|network| {
             fs::create_dir_all(models_dir(network)).unwrap();
             fs::create_dir_all(logs_dir(network)).unwrap();
@@ -47,29 +48,27 @@ impl Node {
             }
             None => open_or_create_secret(node_key_path())?,
         };
+        let agent = AttestationAgent::new(&secret).await?;
         Ok(Node {
             address,
             secret,
-            agent: AttestationAgent::new().await?,
+            agent,
         })
     }
 
     pub async fn register(&self) -> Result<(), Error> {
-        for (network, n) in NONCE_MANAGERS.iter() {
-            let config = NETWORK_CONFIGS.get().unwrap().iter().find(|c| {
-                c.network == *network
-            }).unwrap();
-            let provider = Provider::<Http>::try_from(config.rpc_url.clone()).unwrap();
-            let wallet = AIZEL_CONFIG.wallet_sk.parse::<LocalWallet>().unwrap().with_chain_id(config.chain_id);
-            let middle =  NonceManagerMiddleware::new(provider, wallet.address());
-            let _ = n.initialize_nonce(middle.initialize_nonce(None).await.unwrap());
+        for network in AIZEL_CONFIG.networks.iter() {
+            Contract::sync_nonce(network).await?;
         }
         let tee_type = self.agent.get_tee_type().unwrap();
-        if AIZEL_CONFIG.within_tee {
+        if attestation_enabled() {
             info!(
                 "attestation report {}",
                 self.agent
-                    .get_attestation_report(self.secret.name.encode())
+                    .get_attestation_report(ReportData {
+                        node_pk: self.secret.name.0.to_vec(),
+                        ..Default::default()
+                    })
                     .await?
             );
         }
@@ -0,0 +1,151 @@
+use super::config::{LOG_TAIL_LINES, READY_PROBE_INTERVAL_MILLIS};
+use common::error::Error;
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::process::Child;
+
+/// What tells that a server is ready.
+pub enum Readiness {
+    // the route answers with a success status, the llama server does so once the model is loaded
+    Http(String),
+    // the port accepts connections. The ML server has no health route, so this only tells it is
+    // listening, not that its models are loaded
+    Tcp(u16),
+}
+
+/// How a spawned model server is probed until it serves requests.
+pub struct ReadinessProbe {
+    // server name used in logs and errors
+    pub server: String,
+    pub readiness: Readiness,
+    pub timeout: Duration,
+    // stderr log of the child, its tail is attached to the error when the server fails to start
+    pub stderr_log: PathBuf,
+}
+
+impl ReadinessProbe {
+    /// Polls the server until it is ready, returns the time it took to load.
+    /// Fails early if the child exits, the child is killed if the deadline passes.
+    pub async fn wait(&self, child: &mut Child) -> Result<Duration, Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(READY_PROBE_INTERVAL_MILLIS * 4))
+            .build()
+            .map_err(|e| self.error(format!("failed to create probe client {}", e.to_string())))?;
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    return Err(self.error(format!("exited with {} before it was ready", status)));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    return Err(self.error(format!("failed to check process {}", e.to_string())));
+                }
+            }
+            let ready = match &self.readiness {
+                Readiness::Http(url) => client.get(url).send().await.map(|res| res.status().is_success()).unwrap_or(false),
+                Readiness::Tcp(port) => TcpStream::connect(("127.0.0.1", *port)).await.is_ok(),
+            };
+            if ready {
+                let elapsed = start.elapsed();
+                info!("{} ready in {:.1}s", self.server, elapsed.as_secs_f64());
+                return Ok(elapsed);
+            }
+            if start.elapsed() >= self.timeout {
+                let _ = child.kill().await;
+                return Err(self.error(format!("not ready after {}s", self.timeout.as_secs())));
+            }
+            tokio::time::sleep(Duration::from_millis(READY_PROBE_INTERVAL_MILLIS)).await;
+        }
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::ModelServerError {
+            server: self.server.clone(),
+            message,
+            log_tail: log_tail(&self.stderr_log, LOG_TAIL_LINES),
+        }
+    }
+}
+
+/// Returns the last `lines` lines of a log file, empty if it can't be read.
+pub fn log_tail(path: &Path, lines: usize) -> String {
+    let content = match fs::read(path) {
+        Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+        Err(_) => return String::new(),
+    };
+    let tail: Vec<&str> = content.lines().rev().take(lines).collect();
+    tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use tokio::process::Command;
+
+    fn probe(name: &str, timeout: Duration) -> ReadinessProbe {
+        let stderr_log = std::env::temp_dir().join(format!("aizel_{}_{}.txt", name, std::process::id()));
+        ReadinessProbe {
+            server: name.to_string(),
+            // nothing listens on the discard port
+            readiness: Readiness::Http("http://127.0.0.1:9/v1/models".to_string()),
+            timeout,
+            stderr_log,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_early_exit() {
+        let probe = probe("early_exit", Duration::from_secs(30));
+        let stderr = fs::File::create(&probe.stderr_log).unwrap();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("echo loading; echo out of memory >&2; exit 3")
+            .stdout(Stdio::null())
+            .stderr(Stdio::from(stderr))
+            .spawn()
+            .unwrap();
+        match probe.wait(&mut child).await {
+            Err(Error::ModelServerError { message, log_tail, .. }) => {
+                assert!(message.contains("exit status: 3"));
+                assert_eq!(log_tail, "out of memory");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        fs::remove_file(&probe.stderr_log).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let probe = probe("deadline", Duration::from_secs(1));
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        assert!(probe.wait(&mut child).await.is_err());
+        // the child is killed once the deadline passes
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_readiness() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let probe = ReadinessProbe {
+            readiness: Readiness::Tcp(listener.local_addr().unwrap().port()),
+            ..probe("tcp", Duration::from_secs(5))
+        };
+        let mut child = Command::new("sleep").arg("30").kill_on_drop(true).spawn().unwrap();
+        assert!(probe.wait(&mut child).await.is_ok());
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let path = std::env::temp_dir().join(format!("aizel_log_tail_{}.txt", std::process::id()));
+        fs::write(&path, "a\nb\nc\nd\n").unwrap();
+        assert_eq!(log_tail(&path, 2), "c\nd");
+        assert_eq!(log_tail(&path, 10), "a\nb\nc\nd");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(log_tail(&path, 2), "");
+    }
+}
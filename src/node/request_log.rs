@@ -0,0 +1,215 @@
+use super::aizel::InferenceRequest;
+use super::config::DEFAULT_QUEUE_COMPACT_THRESHOLD;
+use common::error::Error;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogEntry {
+    Append { request: InferenceRequest },
+    Complete { request_id: u64 },
+}
+
+struct LogState {
+    file: File,
+    pending: Vec<InferenceRequest>,
+    completed: usize,
+}
+
+/// Write-ahead log of the requests accepted by a network queue.
+/// Every request is appended before it is queued and marked complete once its result is on chain,
+/// so the requests that were pending when the node stopped can be replayed on startup.
+pub struct RequestLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+fn file_error(path: &Path, e: std::io::Error) -> Error {
+    Error::FileError {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    }
+}
+
+impl RequestLog {
+    /// Opens the log at `path` and returns it together with the pending requests in arrival order.
+    pub fn open(path: PathBuf) -> Result<(Self, Vec<InferenceRequest>), Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| file_error(parent, e))?;
+        }
+        let pending = if path.exists() {
+            Self::replay(&path)?
+        } else {
+            vec![]
+        };
+        let file = Self::rewrite(&path, &pending)?;
+        if !pending.is_empty() {
+            info!("replay {} pending requests from {:?}", pending.len(), path);
+        }
+        Ok((
+            Self {
+                path,
+                state: Mutex::new(LogState {
+                    file,
+                    pending: pending.clone(),
+                    completed: 0,
+                }),
+            },
+            pending,
+        ))
+    }
+
+    pub fn append(&self, request: &InferenceRequest) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        Self::write_entry(
+            &self.path,
+            &mut state.file,
+            &LogEntry::Append {
+                request: request.clone(),
+            },
+        )?;
+        Self::push(&mut state.pending, request.clone());
+        Ok(())
+    }
+
+    pub fn complete(&self, request_id: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        Self::write_entry(&self.path, &mut state.file, &LogEntry::Complete { request_id })?;
+        state.pending.retain(|r| r.request_id != request_id);
+        state.completed += 1;
+        if state.completed >= DEFAULT_QUEUE_COMPACT_THRESHOLD {
+            state.file = Self::rewrite(&self.path, &state.pending)?;
+            state.completed = 0;
+        }
+        Ok(())
+    }
+
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    fn replay(path: &Path) -> Result<Vec<InferenceRequest>, Error> {
+        let file = File::open(path).map_err(|e| file_error(path, e))?;
+        let mut pending: Vec<InferenceRequest> = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| file_error(path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<LogEntry>(&line) {
+                Ok(LogEntry::Append { request }) => Self::push(&mut pending, request),
+                Ok(LogEntry::Complete { request_id }) => pending.retain(|r| r.request_id != request_id),
+                Err(e) => {
+                    // a torn write from a crash can only be the last line
+                    warn!("skip corrupted entry in {:?}: {}", path, e);
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    /// A request that failed may be accepted again, it replaces the previous entry of the same id.
+    fn push(pending: &mut Vec<InferenceRequest>, request: InferenceRequest) {
+        pending.retain(|r| r.request_id != request.request_id);
+        pending.push(request);
+    }
+
+    /// Atomically replaces the log with one holding only `pending`, returns the file opened for appending.
+    fn rewrite(path: &Path, pending: &[InferenceRequest]) -> Result<File, Error> {
+        let tmp_path = path.with_extension("log.tmp");
+        {
+            let mut tmp = File::create(&tmp_path).map_err(|e| file_error(&tmp_path, e))?;
+            for request in pending {
+                Self::write_entry(
+                    &tmp_path,
+                    &mut tmp,
+                    &LogEntry::Append {
+                        request: request.clone(),
+                    },
+                )?;
+            }
+        }
+        fs::rename(&tmp_path, path).map_err(|e| file_error(path, e))?;
+        OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| file_error(path, e))
+    }
+
+    fn write_entry(path: &Path, file: &mut File, entry: &LogEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_string(entry).map_err(|e| Error::SerDeError {
+            message: e.to_string(),
+        })?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|e| file_error(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(request_id: u64) -> InferenceRequest {
+        InferenceRequest {
+            request_id,
+            model_id: 1,
+            input: format!("input-{}", request_id),
+            user_pk: String::new(),
+            req_type: 0,
+            network: "aizel".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_replay_pending_requests() {
+        let dir = std::env::temp_dir().join(format!("aizel_request_log_{}", std::process::id()));
+        let path = dir.join("aizel.log");
+        let _ = fs::remove_dir_all(&dir);
+
+        let (log, pending) = RequestLog::open(path.clone()).unwrap();
+        assert!(pending.is_empty());
+        log.append(&request(1)).unwrap();
+        log.append(&request(2)).unwrap();
+        log.append(&request(3)).unwrap();
+        log.complete(2).unwrap();
+        drop(log);
+
+        // simulate a torn write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"op\":\"append\",\"requ").unwrap();
+        drop(file);
+
+        let (log, pending) = RequestLog::open(path.clone()).unwrap();
+        assert_eq!(
+            pending.iter().map(|r| r.request_id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert_eq!(pending[1].input, "input-3");
+        assert_eq!(log.pending(), 2);
+
+        // a retried request is only logged once
+        let mut retry = request(1);
+        retry.input = "retry-1".to_string();
+        log.append(&retry).unwrap();
+        assert_eq!(log.pending(), 2);
+        drop(log);
+        let (log, pending) = RequestLog::open(path.clone()).unwrap();
+        assert_eq!(
+            pending.iter().map(|r| r.request_id).collect::<Vec<_>>(),
+            vec![3, 1]
+        );
+        assert_eq!(pending[1].input, "retry-1");
+        log.complete(1).unwrap();
+        drop(log);
+        let (_, pending) = RequestLog::open(path.clone()).unwrap();
+        assert_eq!(pending.iter().map(|r| r.request_id).collect::<Vec<_>>(), vec![3]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use super::aizel::{RequestState, RequestStatusResponse, StateTransition};
+use super::config::DEFAULT_REGISTRY_CAPACITY;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct RequestRecord {
+    pub state: RequestState,
+    pub history: Vec<(RequestState, u64)>,
+    pub tx_hash: Option<String>,
+    pub tx_status: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RequestRecord {
+    fn new() -> Self {
+        Self {
+            state: RequestState::Queued,
+            history: vec![(RequestState::Queued, now())],
+            tx_hash: None,
+            tx_status: None,
+            error: None,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state == RequestState::Submitted || self.state == RequestState::Failed
+    }
+
+    fn updated_at(&self) -> u64 {
+        self.history.last().map(|(_, t)| *t).unwrap_or(0)
+    }
+}
+
+/// Tracks every request accepted by the node through its processing states.
+/// Requests are keyed by network and request id since ids are only unique per inference contract.
+pub struct RequestRegistry {
+    records: Mutex<HashMap<(String, u64), RequestRecord>>,
+    capacity: usize,
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+impl RequestRegistry {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_REGISTRY_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Registers a new request in the `queued` state, replacing any previous record with the same id.
+    pub fn queue(&self, network: &str, request_id: u64) {
+        let mut records = self.records.lock().unwrap();
+        self.insert(&mut records, network, request_id);
+    }
+
+    /// Registers the request like `queue` unless it is known and didn't fail, returns its state then.
+    /// Checked and inserted under the same lock, so a request is only accepted once. A request whose
+    /// result transaction may still be mined is `submitting`, not failed, so it is never submitted twice.
+    pub fn try_queue(&self, network: &str, request_id: u64) -> Result<(), RequestState> {
+        let mut records = self.records.lock().unwrap();
+        if let Some(record) = records.get(&(network.to_string(), request_id)) {
+            if record.state != RequestState::Failed {
+                return Err(record.state);
+            }
+        }
+        self.insert(&mut records, network, request_id);
+        Ok(())
+    }
+
+    fn insert(&self, records: &mut HashMap<(String, u64), RequestRecord>, network: &str, request_id: u64) {
+        if records.len() >= self.capacity {
+            // drop the oldest finished request to bound the memory usage, and the oldest one
+            // whatever its state once twice over, requests may never be finalized
+            let over = records.len() >= self.capacity.saturating_mul(2);
+            let oldest = records
+                .iter()
+                .filter(|(_, r)| over || r.is_finished())
+                .min_by_key(|(_, r)| r.updated_at())
+                .map(|(k, _)| k.clone());
+            if let Some(k) = oldest {
+                records.remove(&k);
+            }
+        }
+        records.insert((network.to_string(), request_id), RequestRecord::new());
+    }
+
+    /// Moves a known request to `state`, requests evicted to bound the memory usage are left out.
+    pub fn transition(&self, network: &str, request_id: u64, state: RequestState) {
+        if let Some(record) = self
+            .records
+            .lock()
+            .unwrap()
+            .get_mut(&(network.to_string(), request_id))
+        {
+            record.state = state;
+            record.history.push((state, now()));
+        }
+    }
+
+    pub fn fail(&self, network: &str, request_id: u64, error: String) {
+        if let Some(record) = self
+            .records
+            .lock()
+            .unwrap()
+            .get_mut(&(network.to_string(), request_id))
+        {
+            record.state = RequestState::Failed;
+            record.history.push((RequestState::Failed, now()));
+            record.error = Some(error);
+        }
+    }
+
+    /// Records the final transaction of the result of a request.
+    pub fn set_tx(&self, network: &str, request_id: u64, tx_hash: String, tx_status: String) {
+        if let Some(record) = self
+            .records
+            .lock()
+            .unwrap()
+            .get_mut(&(network.to_string(), request_id))
+        {
+            record.tx_hash = Some(tx_hash);
+            record.tx_status = Some(tx_status);
+        }
+    }
+
+    pub fn get(&self, network: &str, request_id: u64) -> Option<RequestRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(&(network.to_string(), request_id))
+            .cloned()
+    }
+
+    pub fn status(&self, network: &str, request_id: u64) -> Option<RequestStatusResponse> {
+        self.get(network, request_id).map(|r| RequestStatusResponse {
+            request_id,
+            network: network.to_string(),
+            state: r.state as i32,
+            history: r
+                .history
+                .iter()
+                .map(|(state, timestamp)| StateTransition {
+                    state: *state as i32,
+                    timestamp: *timestamp,
+                })
+                .collect(),
+            tx_hash: r.tx_hash.unwrap_or_default(),
+            error: r.error.unwrap_or_default(),
+            tx_status: r.tx_status.unwrap_or_default(),
+        })
+    }
+}
+
+impl Default for RequestRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_lifecycle() {
+        let registry = RequestRegistry::new();
+        registry.queue("aizel", 1);
+        registry.transition("aizel", 1, RequestState::ModelLoading);
+        registry.transition("aizel", 1, RequestState::Running);
+        registry.set_tx("aizel", 1, "0x01".to_string(), "confirmed".to_string());
+        registry.transition("aizel", 1, RequestState::Submitted);
+
+        let status = registry.status("aizel", 1).unwrap();
+        assert_eq!(status.state, RequestState::Submitted as i32);
+        assert_eq!(status.history.len(), 4);
+        assert_eq!(status.tx_hash, "0x01");
+        assert_eq!(status.tx_status, "confirmed");
+        assert!(registry.status("peaq", 1).is_none());
+
+        registry.queue("aizel", 2);
+        registry.fail("aizel", 2, "failed to decrypt input".to_string());
+        let status = registry.status("aizel", 2).unwrap();
+        assert_eq!(status.state, RequestState::Failed as i32);
+        assert_eq!(status.error, "failed to decrypt input");
+    }
+
+    #[test]
+    fn test_try_queue() {
+        let registry = RequestRegistry::new();
+        assert!(registry.try_queue("aizel", 1).is_ok());
+        assert_eq!(registry.try_queue("aizel", 1), Err(RequestState::Queued));
+        assert!(registry.try_queue("peaq", 1).is_ok());
+        registry.transition("aizel", 1, RequestState::Submitting);
+        assert_eq!(registry.try_queue("aizel", 1), Err(RequestState::Submitting));
+        // failed requests may be retried
+        registry.fail("aizel", 1, "error".to_string());
+        assert!(registry.try_queue("aizel", 1).is_ok());
+        assert_eq!(registry.get("aizel", 1).unwrap().state, RequestState::Queued);
+    }
+
+    #[test]
+    fn test_registry_capacity() {
+        let registry = RequestRegistry::with_capacity(2);
+        registry.queue("aizel", 1);
+        registry.fail("aizel", 1, "error".to_string());
+        registry.queue("aizel", 2);
+        registry.queue("aizel", 3);
+        assert!(registry.get("aizel", 1).is_none());
+        assert!(registry.get("aizel", 2).is_some());
+        assert!(registry.get("aizel", 3).is_some());
+
+        // unfinished requests are dropped as well past twice the capacity
+        registry.queue("aizel", 4);
+        assert_eq!(registry.records.lock().unwrap().len(), 3);
+        registry.queue("aizel", 5);
+        assert_eq!(registry.records.lock().unwrap().len(), 4);
+        registry.queue("aizel", 6);
+        assert_eq!(registry.records.lock().unwrap().len(), 4);
+
+        // evicted requests are not recreated by their later transitions
+        let evicted = (1..=6).find(|id| registry.get("aizel", *id).is_none()).unwrap();
+        registry.transition("aizel", evicted, RequestState::Running);
+        registry.fail("aizel", evicted, "error".to_string());
+        assert!(registry.get("aizel", evicted).is_none());
+        assert_eq!(registry.records.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,417 @@
+use super::backend::ModelBackend;
+use crate::chains::contract::ModelInfo;
+use common::error::Error;
+use log::{info, warn};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::{Notify, RwLock, RwLockReadGuard};
+
+pub type Slot = Box<RwLock<dyn ModelBackend>>;
+
+struct SlotState {
+    // model the slot serves or is loading
+    model: Option<u64>,
+    // memory used by the model, known once it is loaded
+    size: u64,
+    last_used: Instant,
+    loading: bool,
+    // evicted, the server is being stopped
+    draining: bool,
+}
+
+/// Servers of a backend, each keeping one model resident. Requests are routed to the server already
+/// holding their model; a model that isn't resident replaces the least recently used one, and
+/// further models are evicted until the resident ones fit in the memory budget.
+/// Requests hold a read lease on their server while they run, so a model is only replaced once
+/// every in flight request for it is done.
+pub struct ModelPool {
+    slots: Vec<Slot>,
+    state: Mutex<Vec<SlotState>>,
+    ram_budget: Option<u64>,
+    // a slot finished loading or draining
+    freed: Notify,
+}
+
+impl ModelPool {
+    pub fn new(slots: Vec<Slot>, ram_budget: Option<u64>) -> Self {
+        let state = slots
+            .iter()
+            .map(|_| SlotState {
+                model: None,
+                size: 0,
+                last_used: Instant::now(),
+                loading: false,
+                draining: false,
+            })
+            .collect();
+        Self {
+            slots,
+            state: Mutex::new(state),
+            ram_budget,
+            freed: Notify::new(),
+        }
+    }
+
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// Whether the model is served or being loaded by one of the servers.
+    pub fn is_resident(&self, model_id: u64) -> bool {
+        self.state.lock().unwrap().iter().any(|s| s.model == Some(model_id))
+    }
+
+    /// Returns a read lease on a server that is serving `model_info`, loading it if needed.
+    /// Only the worker that reserved a slot for the model loads it there.
+    pub async fn acquire(&self, model_info: &ModelInfo) -> Result<RwLockReadGuard<'_, dyn ModelBackend>, Error> {
+        loop {
+            let reserved = self.reserve(model_info.id).await;
+            if let Some(lease) = self.lease(reserved, model_info).await? {
+                return Ok(lease);
+            }
+        }
+    }
+
+    /// Takes a lease on the slot returned by `reserve`, loading the model if the reservation is ours.
+    /// Returns None if the slot was given to another model in the meantime, it has to be reserved again.
+    async fn lease(&self, (index, owned): (usize, bool), model_info: &ModelInfo) -> Result<Option<RwLockReadGuard<'_, dyn ModelBackend>>, Error> {
+        let slot = &self.slots[index];
+        if !owned {
+            let lease = slot.read().await;
+            if lease.model().map(|m| m.id) == Some(model_info.id) {
+                return Ok(Some(lease));
+            }
+            drop(lease);
+            // the slot was given to another model while we waited for the lease, or the server
+            // lost the model, e.g. the supervisor gave up restarting it
+            if !self.claim(index, model_info.id) {
+                return Ok(None);
+            }
+        }
+        let mut backend = slot.write().await;
+        if let Err(e) = self.load(&mut *backend, index, model_info).await {
+            self.release(index, model_info.id);
+            return Err(e);
+        }
+        Ok(Some(backend.downgrade()))
+    }
+
+    async fn load(&self, backend: &mut dyn ModelBackend, index: usize, model_info: &ModelInfo) -> Result<(), Error> {
+        backend.prepare(model_info).await?;
+        let size = backend.model_size(model_info);
+        for evicted in self.evict_for(index, size) {
+            let mut server = self.slots[evicted].write().await;
+            if let Some(model) = server.model() {
+                info!("evict model {} of network {}", model.id, model.network);
+            }
+            let result = server.stop().await;
+            self.state.lock().unwrap()[evicted].draining = false;
+            self.freed.notify_waiters();
+            result?;
+        }
+        backend.start(model_info).await?;
+        {
+            let mut state = self.state.lock().unwrap();
+            state[index].size = size;
+            state[index].loading = false;
+        }
+        self.freed.notify_waiters();
+        Ok(())
+    }
+
+    /// Picks the slot for the model: the one holding it, else a free slot, else the least recently
+    /// used one that isn't busy loading or draining. Waits for a slot to be freed if all are busy,
+    /// or for the model to be loaded if another worker is loading it. Returns whether the slot was
+    /// reserved for the model, the caller has to load it then.
+    async fn reserve(&self, model_id: u64) -> (usize, bool) {
+        loop {
+            // registered before looking, a slot freed in between still wakes us up
+            let freed = self.freed.notified();
+            if let Some(reserved) = self.try_reserve(model_id) {
+                return reserved;
+            }
+            freed.await;
+        }
+    }
+
+    fn try_reserve(&self, model_id: u64) -> Option<(usize, bool)> {
+        let mut state = self.state.lock().unwrap();
+        let (index, owned) = match state.iter().position(|s| s.model == Some(model_id)) {
+            Some(index) if state[index].loading => return None,
+            Some(index) => (index, false),
+            None => {
+                let available = || {
+                    state
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, s)| !s.loading && !s.draining)
+                };
+                let index = available()
+                    .find(|(_, s)| s.model.is_none())
+                    .or_else(|| available().min_by_key(|(_, s)| s.last_used))
+                    .map(|(i, _)| i)?;
+                let slot = &mut state[index];
+                slot.model = Some(model_id);
+                slot.size = 0;
+                slot.loading = true;
+                (index, true)
+            }
+        };
+        state[index].last_used = Instant::now();
+        Some((index, owned))
+    }
+
+    /// Reserves the slot to load the model again if it still holds it and no one else is loading it.
+    fn claim(&self, index: usize, model_id: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let slot = &mut state[index];
+        if slot.model != Some(model_id) || slot.loading || slot.draining {
+            return false;
+        }
+        slot.loading = true;
+        slot.last_used = Instant::now();
+        true
+    }
+
+    /// Frees the slot reserved for the model after its load failed.
+    fn release(&self, index: usize, model_id: u64) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let slot = &mut state[index];
+            if slot.model != Some(model_id) || !slot.loading {
+                return;
+            }
+            slot.model = None;
+            slot.size = 0;
+            slot.loading = false;
+        }
+        self.freed.notify_waiters();
+    }
+
+    /// Marks the least recently used models other than the one in `keep` as evicted until the
+    /// resident models and `size` more fit in the budget.
+    fn evict_for(&self, keep: usize, size: u64) -> Vec<usize> {
+        let budget = match self.ram_budget {
+            Some(budget) => budget,
+            None => return vec![],
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut evicted = vec![];
+        loop {
+            let used: u64 = state
+                .iter()
+                .enumerate()
+                .filter(|(i, s)| *i != keep && !s.draining)
+                .map(|(_, s)| s.size)
+                .sum();
+            if used + size <= budget {
+                break;
+            }
+            let victim = state
+                .iter()
+                .enumerate()
+                .filter(|(i, s)| *i != keep && s.model.is_some() && !s.loading && !s.draining)
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(i, _)| i);
+            match victim {
+                Some(victim) => {
+                    let slot = &mut state[victim];
+                    slot.model = None;
+                    slot.size = 0;
+                    slot.draining = true;
+                    evicted.push(victim);
+                }
+                None => {
+                    warn!("resident models use {} bytes over the memory budget", used + size - budget);
+                    break;
+                }
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::aizel::InferenceRequest;
+    use crate::node::backend::Health;
+    use std::time::Duration;
+    use tokio::sync::mpsc::Sender;
+    use tonic::async_trait;
+
+    fn model(id: u64) -> ModelInfo {
+        ModelInfo {
+            name: format!("model-{}", id),
+            cid: String::new(),
+            id,
+            network: "aizel".to_string(),
+            size: 0,
+        }
+    }
+
+    struct FakeServer {
+        model_info: Option<ModelInfo>,
+    }
+
+    #[async_trait]
+    impl ModelBackend for FakeServer {
+        fn model(&self) -> Option<&ModelInfo> {
+            self.model_info.as_ref()
+        }
+
+        async fn prepare(&self, _model_info: &ModelInfo) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn model_size(&self, model_info: &ModelInfo) -> u64 {
+            model_info.id * 10
+        }
+
+        async fn start(&mut self, model_info: &ModelInfo) -> Result<(), Error> {
+            // let the other workers run while the model loads
+            tokio::task::yield_now().await;
+            self.model_info = Some(model_info.clone());
+            Ok(())
+        }
+
+        fn health(&mut self) -> Health {
+            Health::Up
+        }
+
+        async fn infer(&self, _req: &InferenceRequest, input: String, _deltas: Option<Sender<String>>) -> Result<String, Error> {
+            Ok(input)
+        }
+
+        async fn stop(&mut self) -> Result<(), Error> {
+            self.model_info = None;
+            Ok(())
+        }
+    }
+
+    fn pool(slots: usize, ram_budget: Option<u64>) -> ModelPool {
+        ModelPool::new(
+            (0..slots)
+                .map(|_| -> Slot {
+                    Box::new(RwLock::new(FakeServer { model_info: None }))
+                })
+                .collect(),
+            ram_budget,
+        )
+    }
+
+    async fn resident(pool: &ModelPool) -> Vec<Option<u64>> {
+        let mut models = vec![];
+        for slot in pool.slots() {
+            models.push(slot.read().await.model().map(|m| m.id));
+        }
+        models
+    }
+
+    #[tokio::test]
+    async fn test_lease() {
+        let pool = pool(1, None);
+        drop(pool.acquire(&model(1)).await.unwrap());
+        // requests for the loaded model share the server
+        let first = pool.acquire(&model(1)).await.unwrap();
+        let second = pool.acquire(&model(1)).await.unwrap();
+        assert!(pool.slots()[0].try_write().is_err());
+        drop((first, second));
+
+        let lease = pool.acquire(&model(9)).await.unwrap();
+        assert_eq!(lease.model().unwrap().id, 9);
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        let pool = pool(2, None);
+        drop(pool.acquire(&model(1)).await.unwrap());
+        drop(pool.acquire(&model(2)).await.unwrap());
+        drop(pool.acquire(&model(1)).await.unwrap());
+        assert_eq!(resident(&pool).await, vec![Some(1), Some(2)]);
+        // model 2 is the least recently used
+        drop(pool.acquire(&model(3)).await.unwrap());
+        assert_eq!(resident(&pool).await, vec![Some(1), Some(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_busy_slot() {
+        let pool = pool(1, None);
+        // model 1 is loading in the only slot
+        assert_eq!(pool.reserve(1).await, (0, true));
+        let waiter = pool.reserve(2);
+        tokio::pin!(waiter);
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut waiter).await.is_err());
+        // the same model waits for the load as well
+        assert!(pool.try_reserve(1).is_none());
+        // loading model 1 failed
+        pool.release(0, 1);
+        assert_eq!(waiter.await, (0, true));
+        assert_eq!(pool.state.lock().unwrap()[0].model, Some(2));
+        // only the owner of the reservation frees it
+        pool.release(0, 1);
+        assert_eq!(pool.state.lock().unwrap()[0].model, Some(2));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_workers() {
+        let pool = std::sync::Arc::new(pool(1, None));
+        let workers: Vec<_> = (0..16)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    for _ in 0..20 {
+                        let id = 1 + (i % 2);
+                        let lease = pool.acquire(&model(id)).await.unwrap();
+                        assert_eq!(lease.model().unwrap().id, id);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.await.unwrap();
+        }
+        // the slot serves the model it is recorded with
+        let served = resident(&pool).await[0];
+        let state = pool.state.lock().unwrap();
+        assert_eq!(state[0].model, served);
+        assert!(!state[0].loading);
+    }
+
+    #[tokio::test]
+    async fn test_slot_taken_over() {
+        let pool = pool(1, None);
+        drop(pool.acquire(&model(1)).await.unwrap());
+        // a worker finds model 1 resident, another one replaces it before the first gets its lease
+        let reserved = pool.reserve(1).await;
+        assert_eq!(reserved, (0, false));
+        drop(pool.acquire(&model(2)).await.unwrap());
+        assert!(pool.lease(reserved, &model(1)).await.unwrap().is_none());
+        assert_eq!(resident(&pool).await, vec![Some(2)]);
+        assert_eq!(pool.state.lock().unwrap()[0].model, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_lost_model() {
+        let pool = pool(1, None);
+        drop(pool.acquire(&model(1)).await.unwrap());
+        // the supervisor unloads a model it failed to restart
+        pool.slots()[0].write().await.stop().await.unwrap();
+        let lease = pool.acquire(&model(1)).await.unwrap();
+        assert_eq!(lease.model().unwrap().id, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ram_budget() {
+        let pool = pool(3, Some(50));
+        drop(pool.acquire(&model(1)).await.unwrap());
+        drop(pool.acquire(&model(2)).await.unwrap());
+        assert_eq!(resident(&pool).await, vec![Some(1), Some(2), None]);
+        // 10 + 20 + 30 is over the budget, model 1 has to go although a slot is free
+        drop(pool.acquire(&model(3)).await.unwrap());
+        assert_eq!(resident(&pool).await, vec![None, Some(2), Some(3)]);
+        drop(pool.acquire(&model(1)).await.unwrap());
+        assert_eq!(resident(&pool).await, vec![Some(1), None, Some(3)]);
+    }
+}
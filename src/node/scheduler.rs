@@ -0,0 +1,183 @@
+use super::config::SchedulePolicy;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Identifies the model a request needs, model ids are unique per network.
+pub type ModelKey = u64;
+
+struct Queued<T> {
+    key: ModelKey,
+    queued_at: Instant,
+    item: T,
+}
+
+struct SchedulerState<T> {
+    queue: VecDeque<Queued<T>>,
+}
+
+/// Queue in front of the workers of a network.
+/// With `ModelAffinity` requests for the models resident in the model pools, as told by the workers,
+/// are dispatched first, so that interleaved requests for different models don't relaunch the model
+/// servers on every request.
+/// A request that has waited longer than `max_wait` is always dispatched next.
+/// At most `capacity` requests are queued, callers are pushed back once it is reached.
+pub struct Scheduler<T> {
+    policy: SchedulePolicy,
+    max_wait: Duration,
+    capacity: usize,
+    state: Mutex<SchedulerState<T>>,
+    notify: Notify,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new(policy: SchedulePolicy, max_wait: Duration, capacity: usize) -> Self {
+        Self {
+            policy,
+            max_wait,
+            capacity,
+            state: Mutex::new(SchedulerState {
+                queue: VecDeque::new(),
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.state.lock().unwrap().queue.len() >= self.capacity
+    }
+
+    /// Queues `item` unless the queue is full, the item is handed back then.
+    pub fn push(&self, key: ModelKey, item: T) -> Result<(), T> {
+        let mut state = self.state.lock().unwrap();
+        if state.queue.len() >= self.capacity {
+            return Err(item);
+        }
+        state.queue.push_back(Queued {
+            key,
+            queued_at: Instant::now(),
+            item,
+        });
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Queues `item` whatever the capacity, for the requests replayed on startup that were
+    /// already accepted.
+    pub fn restore(&self, key: ModelKey, item: T) {
+        self.state.lock().unwrap().queue.push_back(Queued {
+            key,
+            queued_at: Instant::now(),
+            item,
+        });
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next request to dispatch, `resident` tells whether a model is resident.
+    pub async fn pop(&self, resident: impl Fn(ModelKey) -> bool) -> T {
+        loop {
+            if let Some(item) = self.try_pop(&resident) {
+                return item;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn try_pop(&self, resident: &dyn Fn(ModelKey) -> bool) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        let index = match self.policy {
+            SchedulePolicy::Fifo => 0,
+            SchedulePolicy::ModelAffinity => {
+                let front = state.queue.front()?;
+                if front.queued_at.elapsed() >= self.max_wait {
+                    0
+                } else {
+                    state
+                        .queue
+                        .iter()
+                        .position(|q| resident(q.key))
+                        .unwrap_or(0)
+                }
+            }
+        };
+        let queued = state.queue.remove(index)?;
+        if !state.queue.is_empty() {
+            // pass the wakeup on in case several requests were pushed while the workers were busy
+            self.notify.notify_one();
+        }
+        Some(queued.item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(scheduler: &Scheduler<u64>, resident: &[u64]) -> Vec<u64> {
+        std::iter::from_fn(|| scheduler.try_pop(&|model| resident.contains(&model))).collect()
+    }
+
+    #[test]
+    fn test_fifo() {
+        let scheduler = Scheduler::new(SchedulePolicy::Fifo, Duration::from_secs(60), 10);
+        for (id, model) in [(1, 1), (2, 9), (3, 1), (4, 9)] {
+            scheduler.push(model, id).unwrap();
+        }
+        assert_eq!(drain(&scheduler, &[9]), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_model_affinity() {
+        let scheduler = Scheduler::new(SchedulePolicy::ModelAffinity, Duration::from_secs(60), 10);
+        let requests = [(1, 1), (2, 9), (3, 1), (4, 5), (5, 9), (6, 1)];
+        for (id, model) in requests {
+            scheduler.push(model, id).unwrap();
+        }
+        // models 1 and 9 are resident, model 5 only once they are drained
+        assert_eq!(drain(&scheduler, &[1, 9]), vec![1, 2, 3, 5, 6, 4]);
+        assert!(scheduler.try_pop(&|_| true).is_none());
+
+        for (id, model) in requests {
+            scheduler.push(model, id).unwrap();
+        }
+        // requests for models that aren't resident are dispatched in order
+        assert_eq!(drain(&scheduler, &[5]), vec![4, 1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn test_max_wait() {
+        let scheduler = Scheduler::new(SchedulePolicy::ModelAffinity, Duration::from_millis(50), 10);
+        scheduler.push(9, 2).unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        scheduler.push(1, 3).unwrap();
+        // request 2 waited too long to be overtaken by the resident model
+        assert_eq!(drain(&scheduler, &[1]), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let scheduler = Scheduler::new(SchedulePolicy::Fifo, Duration::from_secs(60), 2);
+        scheduler.push(1, 1).unwrap();
+        scheduler.push(1, 2).unwrap();
+        assert!(scheduler.is_full());
+        assert_eq!(scheduler.push(1, 3), Err(3));
+        // replayed requests were accepted before and are queued anyway
+        scheduler.restore(1, 4);
+        assert_eq!(drain(&scheduler, &[]), vec![1, 2, 4]);
+        assert!(scheduler.push(1, 5).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let scheduler = std::sync::Arc::new(Scheduler::new(SchedulePolicy::Fifo, Duration::from_secs(60), 10));
+        let waiter = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move { scheduler.pop(|_| false).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        scheduler.push(1, 7).unwrap();
+        assert_eq!(waiter.await.unwrap(), 7);
+    }
+}
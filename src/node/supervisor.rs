@@ -0,0 +1,296 @@
+use super::config::{RESTART_BACKOFF_INITIAL_SECS, RESTART_BACKOFF_MAX_SECS, RESTART_MAX_ATTEMPTS, SUPERVISOR_INTERVAL_MILLIS};
+use super::backend::{Health, ModelBackend};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerHealth {
+    pub up: bool,
+    pub crashes: u64,
+    // exit status of the last crash
+    pub last_exit: Option<String>,
+}
+
+/// Health of the model servers of every network, keyed by network and server name.
+/// A network is unhealthy while any of its servers is down.
+#[derive(Default)]
+pub struct NodeHealth {
+    servers: Mutex<HashMap<(String, String), ServerHealth>>,
+}
+
+impl NodeHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, network: &str, server: &str) {
+        self.servers.lock().unwrap().insert(
+            (network.to_string(), server.to_string()),
+            ServerHealth {
+                up: true,
+                crashes: 0,
+                last_exit: None,
+            },
+        );
+    }
+
+    fn update(&self, network: &str, server: &str, f: impl FnOnce(&mut ServerHealth)) {
+        if let Some(health) = self
+            .servers
+            .lock()
+            .unwrap()
+            .get_mut(&(network.to_string(), server.to_string()))
+        {
+            f(health);
+        }
+    }
+
+    pub fn crashed(&self, network: &str, server: &str, exit: String) {
+        self.update(network, server, |health| {
+            health.up = false;
+            health.crashes += 1;
+            health.last_exit = Some(exit);
+        });
+    }
+
+    pub fn recovered(&self, network: &str, server: &str) {
+        self.update(network, server, |health| health.up = true);
+    }
+
+    pub fn get(&self, network: &str, server: &str) -> Option<ServerHealth> {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(&(network.to_string(), server.to_string()))
+            .cloned()
+    }
+
+    pub fn is_healthy(&self, network: &str) -> bool {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|((n, _), health)| n != network || health.up)
+    }
+}
+
+/// Backoff before the next restart: doubled if the server crashed again shortly after its last
+/// restart, reset once it stayed up longer than the maximum backoff.
+fn next_backoff(backoff: Duration, uptime: Duration) -> Duration {
+    let max = Duration::from_secs(RESTART_BACKOFF_MAX_SECS);
+    if uptime >= max {
+        Duration::from_secs(RESTART_BACKOFF_INITIAL_SECS)
+    } else {
+        (backoff * 2).min(max)
+    }
+}
+
+/// Watches a model backend and restarts it with exponential backoff whenever it goes down.
+/// Backends busy with requests are checked on the next tick, a model switch holds the write lock
+/// so the process it kills is never mistaken for a crash. The write lock is only held while
+/// restarting, not while backing off, and a model that fails to restart `RESTART_MAX_ATTEMPTS`
+/// times is unloaded so the backend can serve other models.
+pub async fn supervise<B: ModelBackend + ?Sized>(server: &RwLock<B>, network: &str, name: &str, health: &NodeHealth) {
+    health.register(network, name);
+    let initial = Duration::from_secs(RESTART_BACKOFF_INITIAL_SECS);
+    // the backoff is doubled before the first restart
+    let mut backoff = initial / 2;
+    let mut started_at = Instant::now();
+    loop {
+        tokio::time::sleep(Duration::from_millis(SUPERVISOR_INTERVAL_MILLIS)).await;
+        let (status, model_info) = {
+            let mut server = match server.try_write() {
+                Ok(server) => server,
+                Err(_) => continue,
+            };
+            let status = match server.health() {
+                Health::Down(status) => status,
+                Health::Up => continue,
+            };
+            match server.model() {
+                Some(model_info) => (status, model_info.clone()),
+                None => continue,
+            }
+        };
+        health.crashed(network, name, status.clone());
+        backoff = next_backoff(backoff, started_at.elapsed());
+        let crashes = health.get(network, name).map(|h| h.crashes).unwrap_or(0);
+        error!(
+            "{} of network {} exited with {} ({} crashes), restart in {}s",
+            name, network, status, crashes, backoff.as_secs()
+        );
+        let mut attempts = 0;
+        loop {
+            tokio::time::sleep(backoff).await;
+            let mut server = server.write().await;
+            // a worker may have switched or reloaded the model while backing off
+            let down = matches!(server.health(), Health::Down(_));
+            if !down || server.model().map(|m| m.id) != Some(model_info.id) {
+                started_at = Instant::now();
+                info!("{} of network {} was reloaded", name, network);
+                break;
+            }
+            match server.start(&model_info).await {
+                Ok(()) => {
+                    started_at = Instant::now();
+                    info!("{} of network {} restarted", name, network);
+                    break;
+                }
+                Err(e) => {
+                    attempts += 1;
+                    if attempts >= RESTART_MAX_ATTEMPTS {
+                        error!(
+                            "give up restarting {} of network {} after {} attempts, unload model {}: {}",
+                            name, network, attempts, model_info.id, e
+                        );
+                        if let Err(e) = server.stop().await {
+                            error!("failed to unload model {}: {}", model_info.id, e);
+                        }
+                        break;
+                    }
+                    backoff = next_backoff(backoff, Duration::ZERO);
+                    warn!(
+                        "failed to restart {} of network {}, retry in {}s: {}",
+                        name, network, backoff.as_secs(), e.to_string()
+                    );
+                }
+            }
+        }
+        health.recovered(network, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::contract::ModelInfo;
+    use crate::node::aizel::InferenceRequest;
+    use common::error::Error;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::Sender;
+    use tonic::async_trait;
+
+    struct CrashingServer {
+        model_info: Option<ModelInfo>,
+        // restart attempts left to fail
+        failures: u32,
+        exited: bool,
+        restarts: u32,
+    }
+
+    #[async_trait]
+    impl ModelBackend for CrashingServer {
+        fn model(&self) -> Option<&ModelInfo> {
+            self.model_info.as_ref()
+        }
+
+        async fn prepare(&self, _model_info: &ModelInfo) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn health(&mut self) -> Health {
+            match self.exited {
+                true => Health::Down(ExitStatus::from_raw(9).to_string()),
+                false => Health::Up,
+            }
+        }
+
+        async fn infer(&self, _req: &InferenceRequest, input: String, _deltas: Option<Sender<String>>) -> Result<String, Error> {
+            Ok(input)
+        }
+
+        async fn stop(&mut self) -> Result<(), Error> {
+            self.model_info = None;
+            self.exited = false;
+            Ok(())
+        }
+
+        async fn start(&mut self, _model_info: &ModelInfo) -> Result<(), Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::InferenceError {
+                    message: "out of memory".to_string(),
+                });
+            }
+            self.exited = false;
+            self.restarts += 1;
+            Ok(())
+        }
+    }
+
+    fn crashing_server(failures: u32) -> Arc<RwLock<CrashingServer>> {
+        Arc::new(RwLock::new(CrashingServer {
+            model_info: Some(ModelInfo {
+                name: "llama".to_string(),
+                cid: String::new(),
+                id: 1,
+                network: "aizel".to_string(),
+                size: 0,
+            }),
+            failures,
+            exited: false,
+            restarts: 0,
+        }))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart_crashed_server() {
+        let server = crashing_server(2);
+        let health = Arc::new(NodeHealth::new());
+        {
+            let (server, health) = (server.clone(), health.clone());
+            tokio::spawn(async move { supervise(&*server, "aizel", "llama server", &health).await });
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(health.is_healthy("aizel"));
+
+        server.write().await.exited = true;
+        tokio::time::sleep(Duration::from_millis(SUPERVISOR_INTERVAL_MILLIS * 2)).await;
+        assert!(!health.is_healthy("aizel"));
+        assert!(health.is_healthy("avax"));
+
+        // two failed attempts back off 1s, 2s then 4s
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(health.is_healthy("aizel"));
+        let status = health.get("aizel", "llama server").unwrap();
+        assert_eq!(status.crashes, 1);
+        assert!(status.last_exit.unwrap().contains("signal: 9"));
+        assert_eq!(server.read().await.restarts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_give_up_restarting() {
+        let server = crashing_server(u32::MAX);
+        let health = Arc::new(NodeHealth::new());
+        {
+            let (server, health) = (server.clone(), health.clone());
+            tokio::spawn(async move { supervise(&*server, "aizel", "llama server", &health).await });
+        }
+        server.write().await.exited = true;
+        tokio::time::sleep(Duration::from_millis(SUPERVISOR_INTERVAL_MILLIS * 2)).await;
+        assert!(!health.is_healthy("aizel"));
+        // the lock is free while backing off, workers aren't blocked behind the restarts
+        assert!(server.try_write().is_ok());
+
+        // restarts back off 1s, 2s, 4s, 8s and 16s before giving up
+        tokio::time::sleep(Duration::from_secs(40)).await;
+        let server = server.read().await;
+        assert!(server.model_info.is_none());
+        assert_eq!(server.restarts, 0);
+        assert!(health.is_healthy("aizel"));
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        let initial = Duration::from_secs(RESTART_BACKOFF_INITIAL_SECS);
+        let max = Duration::from_secs(RESTART_BACKOFF_MAX_SECS);
+        assert_eq!(next_backoff(initial, Duration::ZERO), initial * 2);
+        assert_eq!(next_backoff(max, Duration::ZERO), max);
+        assert_eq!(next_backoff(max, max), initial);
+    }
+}
@@ -0,0 +1,160 @@
+use super::aizel::InferenceRequest;
+use super::config::{request_validation, AIZEL_CONFIG};
+use crate::chains::contract::{Contract, OnChainRequest};
+use common::error::Error;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tonic::Status;
+
+fn to_status(e: Error) -> Status {
+    match e {
+        Error::NetworkConfigNotFoundError { network } => Status::invalid_argument(format!("unkown network {}", network)),
+        e @ Error::InvalidArgumentError { .. } => Status::invalid_argument(e.to_string()),
+        e => Status::unavailable(format!("failed to query the inference contract {}", e)),
+    }
+}
+
+/// Checks a request pushed to the node against the one recorded on chain, so no work is done or
+/// gas spent for requests that don't exist, belong to another node or are already settled.
+#[allow(clippy::result_large_err)]
+pub fn check(req: &InferenceRequest, onchain: Option<&OnChainRequest>, node_id: u64) -> Result<(), Status> {
+    let onchain = onchain.ok_or(Status::not_found(format!(
+        "request {} doesn't exist on network {}",
+        req.request_id, req.network
+    )))?;
+    if onchain.node_id != node_id {
+        return Err(Status::permission_denied(format!(
+            "request {} is assigned to node {}",
+            req.request_id, onchain.node_id
+        )));
+    }
+    if onchain.settled {
+        return Err(Status::failed_precondition(format!("request {} is already settled", req.request_id)));
+    }
+    let mut mismatches = vec![];
+    if onchain.model_id != req.model_id {
+        mismatches.push("model_id");
+    }
+    if onchain.user_pk != req.user_pk {
+        mismatches.push("user_pk");
+    }
+    if onchain.input != req.input {
+        mismatches.push("input");
+    }
+    if onchain.req_type as i32 != req.req_type {
+        mismatches.push("req_type");
+    }
+    if !mismatches.is_empty() {
+        return Err(Status::invalid_argument(format!(
+            "{} of request {} don't match the chain",
+            mismatches.join(", "),
+            req.request_id
+        )));
+    }
+    Ok(())
+}
+
+/// Whether the requests are validated: as configured, or if not set when the contracts of every
+/// network implement what validation relies on. Enabling it on contracts that don't is an error.
+pub async fn validation_enabled() -> Result<bool, Error> {
+    let configured = request_validation();
+    if configured == Some(false) {
+        return Ok(false);
+    }
+    let mut missing = vec![];
+    for network in AIZEL_CONFIG.networks.iter() {
+        for signature in Contract::missing_request_bindings(network).await? {
+            missing.push(format!("{} on network {}", signature, network));
+        }
+    }
+    if missing.is_empty() {
+        info!("validate the requests against the inference contracts");
+        return Ok(true);
+    }
+    let message = format!("the contracts don't implement {}", missing.join(", "));
+    match configured {
+        Some(_) => Err(Error::ContractError {
+            message: format!("can't validate the requests, {}", message),
+        }),
+        None => {
+            warn!("requests are not validated, {}", message);
+            Ok(false)
+        }
+    }
+}
+
+/// Validates the requests pushed over gRPC against the inference contract.
+pub struct RequestValidator {
+    public_key: String,
+    // ids the node registered with, per network
+    node_ids: Mutex<HashMap<String, u64>>,
+}
+
+impl RequestValidator {
+    pub fn new(public_key: String) -> Self {
+        Self {
+            public_key,
+            node_ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn node_id(&self, network: &str) -> Result<u64, Status> {
+        if let Some(id) = self.node_ids.lock().unwrap().get(network) {
+            return Ok(*id);
+        }
+        let id = Contract::query_node_id(self.public_key.clone(), network).await.map_err(to_status)?;
+        if id == 0 {
+            return Err(Status::unavailable(format!("node is not registered on network {} yet", network)));
+        }
+        self.node_ids.lock().unwrap().insert(network.to_string(), id);
+        Ok(id)
+    }
+
+    pub async fn validate(&self, req: &InferenceRequest) -> Result<(), Status> {
+        let node_id = self.node_id(&req.network).await?;
+        let onchain = Contract::query_request(req.request_id, &req.network).await.map_err(to_status)?;
+        check(req, onchain.as_ref(), node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    #[test]
+    fn test_check() {
+        let req = InferenceRequest {
+            request_id: 5,
+            model_id: 2,
+            input: "ciphertext".to_string(),
+            user_pk: "pk".to_string(),
+            req_type: 0,
+            network: "aizel".to_string(),
+        };
+        let onchain = OnChainRequest {
+            node_id: 1,
+            model_id: 2,
+            user_pk: "pk".to_string(),
+            input: "ciphertext".to_string(),
+            req_type: 0,
+            settled: false,
+        };
+        assert!(check(&req, Some(&onchain), 1).is_ok());
+        assert_eq!(check(&req, None, 1).unwrap_err().code(), Code::NotFound);
+        assert_eq!(check(&req, Some(&onchain), 3).unwrap_err().code(), Code::PermissionDenied);
+
+        let settled = OnChainRequest { settled: true, ..onchain.clone() };
+        assert_eq!(check(&req, Some(&settled), 1).unwrap_err().code(), Code::FailedPrecondition);
+
+        let forged = InferenceRequest {
+            model_id: 3,
+            input: "other".to_string(),
+            ..req
+        };
+        let status = check(&forged, Some(&onchain), 1).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().starts_with("model_id, input"));
+    }
+}
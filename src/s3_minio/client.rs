@@ -41,15 +41,24 @@ pub struct MinioClient {
 }
 
 impl MinioClient {
-    fn new(url: String, account_info: Option<(String, String)>) -> Self {
-        let data_node_url = url.parse::<BaseUrl>().unwrap();
+    pub fn new(url: String, account_info: Option<(String, String)>) -> Self {
+        Self::try_new(url, account_info).unwrap()
+    }
+
+    /// Client of a data node url that may be malformed, e.g. given by a user.
+    pub fn try_new(url: String, account_info: Option<(String, String)>) -> Result<Self, Error> {
+        let invalid = |message: String| Error::InvalidArgumentError {
+            argument: "data_node_url".to_string(),
+            message,
+        };
+        let data_node_url = url.parse::<BaseUrl>().map_err(|e| invalid(e.to_string()))?;
         let static_provider = match account_info {
             Some((account, password)) => StaticProvider::new(&account, &password, None),
             None => StaticProvider::new("", "", None),
         };
-        let client =
-            Client::new(data_node_url, Some(Box::new(static_provider)), None, None).unwrap();
-        MinioClient { client }
+        let client = Client::new(data_node_url, Some(Box::new(static_provider)), None, None)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(MinioClient { client })
     }
 
     pub async fn get_data_client(network: &str) -> MinioClient {
@@ -104,6 +113,20 @@ impl MinioClient {
         // Ok(InputInfo::default())
     }
 
+    pub async fn get_object(&self, bucket_name: &str, object_name: &str) -> Result<String, Error> {
+        self.bucket_exists(bucket_name).await?;
+        let resp = self
+            .client
+            .get_object_old(&GetObjectArgs::new(bucket_name, object_name).unwrap())
+            .await
+            .map_err(|e| Error::MinIOError {
+                message: format!("failed to get object {}", e),
+            })?;
+        resp.text().await.map_err(|e| Error::MinIOError {
+            message: format!("failed to read object {}", e),
+        })
+    }
+
     pub async fn upload(
         &self,
         bucket_name: &str,
@@ -1,17 +1,22 @@
+use super::measurement;
 use common::error::{AttestationError, Error};
+use common::tee::event_log::{Rtmr, RtmrEvent, TdxReport, RTMR_SIZE};
+use common::tee::report_data::ReportData;
 use common::tee::{provider::TEEProvider, TEEType};
 use log::{error, info};
-use sha256::digest;
 use std::future::Future;
+use std::mem;
 use std::pin::Pin;
+
+// offset of RTMR0 in the TDREPORT: REPORTMACSTRUCT, TEE_TCB_INFO and reserved bytes, then the
+// attributes, xfam and the MRTD, MRCONFIGID, MROWNER, MROWNERCONFIG measurements of TDINFO
+const TDREPORT_RTMR_OFFSET: usize = 512 + 16 + 4 * 48;
 #[derive(Debug)]
 pub struct AliCloud {}
 
-async fn internal_get_report(nonce: String) -> Result<String, Error> {
-    let mut d: Vec<u8> = hex::decode(digest(&nonce)).unwrap();
-    d.resize(64, 0);
+async fn internal_get_report(data: ReportData) -> Result<String, Error> {
     let report_data = tdx_attest_rs::tdx_report_data_t {
-        d: d.try_into().unwrap(),
+        d: data.tdx_report_data(),
     };
     let mut tdx_report = tdx_attest_rs::tdx_report_t { d: [0; 1024usize] };
     let result = tdx_attest_rs::tdx_att_get_report(Some(&report_data), &mut tdx_report);
@@ -43,7 +48,12 @@ async fn internal_get_report(nonce: String) -> Result<String, Error> {
     match quote {
         Some(q) => {
             info!("Successfully get the TD Quote.");
-            Ok(hex::encode(&q))
+            let report = TdxReport {
+                quote: hex::encode(&q),
+                event_log: measurement::event_log(),
+                report_data: hex::encode(data.encode()),
+            };
+            Ok(serde_json::to_string(&report).unwrap())
         }
         None => {
             error!("failed to get the quote.");
@@ -57,10 +67,55 @@ async fn internal_get_report(nonce: String) -> Result<String, Error> {
     }
 }
 
+fn tdx_error(message: String) -> Error {
+    Error::AttestationError {
+        teetype: TEEType::AliCloud,
+        error: AttestationError::ReportError { message },
+    }
+}
+
+/// Serializes the header `tdx_att_extend` takes for the event. The driver rejects events carrying
+/// data, only the sha384 of the data is sent, the data itself is kept in the local event log.
+fn rtmr_event_buf(event: &RtmrEvent) -> Vec<u8> {
+    let header_size = mem::size_of::<tdx_attest_rs::tdx_rtmr_event_t>();
+    let mut buf = vec![0u8; header_size];
+    unsafe {
+        let mut header: tdx_attest_rs::tdx_rtmr_event_t = mem::zeroed();
+        header.version = 1;
+        header.rtmr_index = event.rtmr_index;
+        header.extend_data = event.extend_data();
+        header.event_type = event.event_type;
+        header.event_data_size = 0;
+        std::ptr::copy_nonoverlapping(&header as *const _ as *const u8, buf.as_mut_ptr(), header_size);
+    }
+    buf
+}
+
+/// Extends the RTMR of the event with the sha384 of its data.
+pub fn extend_rtmr(event: &RtmrEvent) -> Result<(), Error> {
+    let result = tdx_attest_rs::tdx_att_extend(&rtmr_event_buf(event));
+    if result != tdx_attest_rs::tdx_attest_error_t::TDX_ATTEST_SUCCESS {
+        return Err(tdx_error(format!("failed to extend rtmr {}: {:?}", event.rtmr_index, result)));
+    }
+    Ok(())
+}
+
+/// Current value of the RTMR `index`, read from a fresh TD report.
+pub fn current_rtmr(index: u64) -> Result<Rtmr, Error> {
+    let report_data = tdx_attest_rs::tdx_report_data_t { d: [0; 64usize] };
+    let mut tdx_report = tdx_attest_rs::tdx_report_t { d: [0; 1024usize] };
+    let result = tdx_attest_rs::tdx_att_get_report(Some(&report_data), &mut tdx_report);
+    if result != tdx_attest_rs::tdx_attest_error_t::TDX_ATTEST_SUCCESS {
+        return Err(tdx_error(format!("failed to get tdx report: {:?}", result)));
+    }
+    let offset = TDREPORT_RTMR_OFFSET + index as usize * RTMR_SIZE;
+    Ok(tdx_report.d[offset..offset + RTMR_SIZE].try_into().unwrap())
+}
+
 impl TEEProvider for AliCloud {
     fn get_report(
         &self,
-        nonce: String,
+        report_data: ReportData,
     ) -> Pin<
         Box<
             (dyn Future<Output = std::result::Result<std::string::String, common::error::Error>>
@@ -68,10 +123,35 @@ impl TEEProvider for AliCloud {
                  + 'static),
         >,
     > {
-        Box::pin(internal_get_report(nonce))
+        Box::pin(internal_get_report(report_data))
     }
 
     fn get_type(&self) -> Result<TEEType, Error> {
         Ok(TEEType::AliCloud)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tee::event_log::ModelMeasurement;
+
+    #[test]
+    fn test_rtmr_event_buf() {
+        let event = RtmrEvent::model(&ModelMeasurement {
+            network: "aizel".to_string(),
+            model_id: 1,
+            name: "model".to_string(),
+            digest: "00".repeat(32),
+        });
+        let buf = rtmr_event_buf(&event);
+        // only the header, the event data stays out of the ioctl
+        assert_eq!(buf.len(), mem::size_of::<tdx_attest_rs::tdx_rtmr_event_t>());
+        let header: tdx_attest_rs::tdx_rtmr_event_t = unsafe { std::ptr::read(buf.as_ptr() as *const _) };
+        assert_eq!(header.version, 1);
+        assert_eq!(header.rtmr_index, event.rtmr_index);
+        assert_eq!(header.extend_data, event.extend_data());
+        assert_eq!(header.event_type, event.event_type);
+        assert_eq!(header.event_data_size, 0);
+    }
+}
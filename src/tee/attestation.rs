@@ -1,18 +1,37 @@
 use super::alicloud::AliCloud;
 use super::gcp::GCP;
+use super::measurement;
+use super::mock::Mock;
+use super::sev_snp::SevSnp;
+use crate::crypto::secret::Secret;
+use crate::node::config::{tee_provider, TeeProvider, AIZEL_CONFIG};
 use common::error::Error;
+use common::tee::detect::get_current_tee_type;
+use common::tee::report_data::ReportData;
 use common::tee::{provider::TEEProvider, TEEType};
-use reqwest::header::HeaderMap;
 pub struct AttestationAgent {
     provider: Box<dyn TEEProvider>,
 }
 
 impl AttestationAgent {
-    pub async fn new() -> Result<AttestationAgent, Error> {
-        let tee_type = get_current_tee_type().await?;
+    pub async fn new(secret: &Secret) -> Result<AttestationAgent, Error> {
+        let tee_type = match tee_provider() {
+            TeeProvider::Gcp => TEEType::GCP,
+            TeeProvider::AlicloudTdx => TEEType::AliCloud,
+            TeeProvider::Mock => TEEType::Mock,
+            TeeProvider::SevSnp => TEEType::SevSnp,
+            TeeProvider::Auto => get_current_tee_type().await?,
+        };
         let provider: Box<dyn TEEProvider> = match tee_type {
             TEEType::GCP => Box::new(GCP {}),
-            TEEType::AliCloud => Box::new(AliCloud {}),
+            TEEType::AliCloud => {
+                if AIZEL_CONFIG.within_tee {
+                    measurement::enable()?;
+                }
+                Box::new(AliCloud {})
+            }
+            TEEType::Mock => Box::new(Mock::new(secret.secret.clone())),
+            TEEType::SevSnp => Box::new(SevSnp {}),
             TEEType::Unkown => {
                 return Err(Error::UnkownTEETypeERROR {
                     message: format!("Unkown TEE provider"),
@@ -22,8 +41,8 @@ impl AttestationAgent {
         Ok(AttestationAgent { provider })
     }
 
-    pub async fn get_attestation_report(&self, nonce: String) -> Result<String, Error> {
-        self.provider.get_report(nonce).await
+    pub async fn get_attestation_report(&self, report_data: ReportData) -> Result<String, Error> {
+        self.provider.get_report(report_data).await
     }
 
     pub fn get_tee_type(&self) -> Result<i32, Error> {
@@ -31,55 +50,6 @@ impl AttestationAgent {
     }
 }
 
-pub async fn get_current_tee_type() -> Result<TEEType, Error> {
-    // Try GCP
-    {
-        let mut headers = HeaderMap::new();
-        headers.insert("Metadata-Flavor", "Google".parse().unwrap());
-        let client = reqwest::Client::new();
-        let response = client
-            .get("http://metadata.google.internal/computeMetadata/v1/instance/")
-            .headers(headers)
-            .send()
-            .await
-            .map_err(|e| Error::UnkownTEETypeERROR {
-                message: e.to_string(),
-            });
-        match response {
-            Ok(res) => {
-                if res.status().is_success() {
-                    return Ok(TEEType::GCP);
-                }
-            }
-            Err(_) => {}
-        }
-    }
-
-    // Try AliCloud
-    {
-        let client = reqwest::Client::new();
-        let response = client
-            .get("http://100.100.100.200/latest/meta-data")
-            .send()
-            .await
-            .map_err(|e| Error::UnkownTEETypeERROR {
-                message: e.to_string(),
-            });
-        match response {
-            Ok(res) => {
-                if res.status().is_success() {
-                    return Ok(TEEType::AliCloud);
-                }
-            }
-            Err(_) => {}
-        }
-    }
-
-    return Err(Error::UnkownTEETypeERROR {
-        message: "unkown tee type".to_string(),
-    });
-}
-
 #[tokio::test]
 async fn test_get_input() {
     println!("{}", get_current_tee_type().await.unwrap());
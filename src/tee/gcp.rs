@@ -1,11 +1,11 @@
 use common::error::{AttestationError, Error};
+use common::tee::report_data::ReportData;
 use common::tee::{provider::TEEProvider, TEEType};
 use http_body_util::{BodyExt, Full};
 use hyper::body::Bytes;
 use hyper::client::conn::http1::handshake;
 use hyper_util::rt::TokioIo;
 use serde::Serialize;
-use sha256::digest;
 use std::future::Future;
 use std::path::Path;
 use std::pin::Pin;
@@ -25,10 +25,10 @@ struct CustomToken {
     token_type: String,
 }
 
-async fn internal_get_report(nonce: String) -> Result<String, Error> {
+async fn internal_get_report(report_data: ReportData) -> Result<String, Error> {
     let request = CustomToken {
         audience: AIZEL_DEFAULT_AUDIENCE.to_string(),
-        nonces: vec![digest(&nonce)],
+        nonces: vec![report_data.nonce()],
         token_type: "OIDC".to_string(),
     };
     let custom_json = serde_json::to_string(&request).unwrap();
@@ -93,7 +93,7 @@ async fn internal_get_report(nonce: String) -> Result<String, Error> {
 impl TEEProvider for GCP {
     fn get_report(
         &self,
-        nonce: String,
+        report_data: ReportData,
     ) -> Pin<
         Box<
             (dyn Future<Output = std::result::Result<std::string::String, common::error::Error>>
@@ -101,7 +101,7 @@ impl TEEProvider for GCP {
                  + 'static),
         >,
     > {
-        Box::pin(internal_get_report(nonce))
+        Box::pin(internal_get_report(report_data))
 
         // TODO: TO BE DELETED
         // Ok(String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap())
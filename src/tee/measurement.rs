@@ -0,0 +1,84 @@
+use super::alicloud::{current_rtmr, extend_rtmr};
+use crate::chains::contract::ModelInfo;
+use crate::node::config::root_dir;
+use common::error::Error;
+use common::tee::event_log::{replay, ModelMeasurement, RtmrEvent, MODEL_RTMR_INDEX, RTMR_SIZE};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const EVENT_LOG_FILENAME: &str = "event_log.json";
+
+lazy_static! {
+    // events extended into the RTMRs, None until measurement is enabled
+    static ref EVENT_LOG: Mutex<Option<Vec<RtmrEvent>>> = Mutex::new(None);
+}
+
+pub fn event_log_path() -> PathBuf {
+    root_dir().join(EVENT_LOG_FILENAME)
+}
+
+fn load_event_log() -> Vec<RtmrEvent> {
+    fs::read_to_string(event_log_path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_event_log(event_log: &[RtmrEvent]) -> Result<(), Error> {
+    fs::write(event_log_path(), serde_json::to_string(event_log).unwrap()).map_err(|e| Error::FileError {
+        path: event_log_path(),
+        message: e.to_string(),
+    })
+}
+
+/// Starts measuring the models loaded by the node into the TDX RTMR. The RTMR keeps its value
+/// until the TD reboots, so the event log a previous run of the node left is resumed if it still
+/// replays to the current RTMR.
+pub fn enable() -> Result<(), Error> {
+    let mut guard = EVENT_LOG.lock().unwrap();
+    if guard.is_some() {
+        return Ok(());
+    }
+    let rtmr = current_rtmr(MODEL_RTMR_INDEX)?;
+    let mut event_log = load_event_log();
+    if replay(&event_log, MODEL_RTMR_INDEX).ok() != Some(rtmr) {
+        if !event_log.is_empty() {
+            warn!("event log at {:?} doesn't match rtmr {}, discard it", event_log_path(), MODEL_RTMR_INDEX);
+        }
+        event_log.clear();
+        if rtmr != [0; RTMR_SIZE] {
+            warn!("rtmr {} was extended outside the node, quotes can't be replayed", MODEL_RTMR_INDEX);
+        }
+    }
+    save_event_log(&event_log)?;
+    *guard = Some(event_log);
+    Ok(())
+}
+
+/// Extends the RTMR with the model about to be served and records the event, if measurement is enabled.
+pub fn measure_model(model_info: &ModelInfo, digest: &str) -> Result<(), Error> {
+    let mut guard = EVENT_LOG.lock().unwrap();
+    let event_log = match guard.as_mut() {
+        Some(event_log) => event_log,
+        None => return Ok(()),
+    };
+    let event = RtmrEvent::model(&ModelMeasurement {
+        network: model_info.network.clone(),
+        model_id: model_info.id,
+        name: model_info.name.clone(),
+        digest: digest.to_string(),
+    });
+    extend_rtmr(&event)?;
+    event_log.push(event);
+    save_event_log(event_log)?;
+    info!("model {} of network {} measured into rtmr {}", model_info.id, model_info.network, MODEL_RTMR_INDEX);
+    Ok(())
+}
+
+/// Events extended so far, to be sent along with quotes.
+pub fn event_log() -> Vec<RtmrEvent> {
+    EVENT_LOG.lock().unwrap().clone().unwrap_or_default()
+}
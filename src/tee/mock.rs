@@ -0,0 +1,72 @@
+use crate::crypto::digest::Digest;
+use crate::crypto::key::SecretKey;
+use crate::crypto::signature::Signature;
+use common::error::Error;
+use common::tee::mock::MockReport;
+use common::tee::report_data::ReportData;
+use common::tee::{provider::TEEProvider, TEEType};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Signs the report_data with the node key in place of a TEE. Reports are deterministic, the
+/// same report_data always yields the same report.
+pub struct Mock {
+    secret: SecretKey,
+}
+
+impl Mock {
+    pub fn new(secret: SecretKey) -> Self {
+        Self { secret }
+    }
+
+    pub fn report(&self, report_data: &ReportData) -> String {
+        let signature = Signature::new(&Digest(report_data.digest()), &self.secret);
+        let report = MockReport {
+            report_data: hex::encode(report_data.encode()),
+            signature: hex::encode(signature.flatten()),
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+}
+
+impl TEEProvider for Mock {
+    fn get_report(
+        &self,
+        report_data: ReportData,
+    ) -> Pin<Box<dyn Future<Output = Result<String, Error>> + Send>> {
+        let report = self.report(&report_data);
+        Box::pin(async move { Ok(report) })
+    }
+
+    fn get_type(&self) -> Result<TEEType, Error> {
+        Ok(TEEType::Mock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::secret::Secret;
+
+    #[tokio::test]
+    async fn test_mock_report() {
+        let secret = Secret::new();
+        let mock = Mock::new(secret.secret.clone());
+        let report_data = ReportData {
+            node_pk: secret.name.0.to_vec(),
+            request_id: 1,
+            ..Default::default()
+        };
+        let report = mock.get_report(report_data.clone()).await.unwrap();
+        assert_eq!(report, mock.report(&report_data));
+
+        let report: MockReport = serde_json::from_str(&report).unwrap();
+        assert_eq!(hex::decode(report.report_data).unwrap(), report_data.encode());
+        let signature = secp256k1::ecdsa::Signature::from_compact(&hex::decode(report.signature).unwrap()).unwrap();
+        let message = secp256k1::Message::from_digest(report_data.digest());
+        let key = secp256k1::PublicKey::from_slice(&secret.name.0).unwrap();
+        assert!(secp256k1::Secp256k1::verification_only()
+            .verify_ecdsa(&message, &signature, &key)
+            .is_ok());
+    }
+}
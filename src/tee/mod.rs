@@ -1,3 +1,6 @@
 pub mod alicloud;
 pub mod attestation;
 pub mod gcp;
+pub mod measurement;
+pub mod mock;
+pub mod sev_snp;
@@ -0,0 +1,109 @@
+use common::error::{AttestationError, Error};
+use common::tee::detect::SEV_GUEST_DEVICE;
+use common::tee::report_data::ReportData;
+use common::tee::sev_snp::SevSnpReport;
+use common::tee::{provider::TEEProvider, TEEType};
+use log::info;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::os::fd::AsRawFd;
+use std::pin::Pin;
+
+// _IOWR('S', 0x0, struct snp_guest_request_ioctl) of linux/sev-guest.h
+const SNP_GET_REPORT: u64 = 0xC020_5300;
+const SNP_MSG_VERSION: u8 = 1;
+const SNP_REPORT_RESP_SIZE: usize = 4000;
+// status, report_size and reserved bytes of msg_report_rsp before the report
+const SNP_REPORT_OFFSET: usize = 0x20;
+
+#[repr(C)]
+struct SnpReportReq {
+    user_data: [u8; 64],
+    vmpl: u32,
+    rsvd: [u8; 28],
+}
+
+#[repr(C)]
+struct SnpReportResp {
+    data: [u8; SNP_REPORT_RESP_SIZE],
+}
+
+#[repr(C)]
+struct SnpGuestRequestIoctl {
+    msg_version: u8,
+    req_data: u64,
+    resp_data: u64,
+    // firmware and vmm error codes
+    exitinfo2: u64,
+}
+
+#[derive(Debug)]
+pub struct SevSnp {}
+
+fn report_error(message: String) -> Error {
+    Error::AttestationError {
+        teetype: TEEType::SevSnp,
+        error: AttestationError::ReportError { message },
+    }
+}
+
+/// Asks the AMD secure processor for an attestation report over `user_data`, at VMPL 0.
+fn get_snp_report(user_data: [u8; 64]) -> Result<Vec<u8>, Error> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(SEV_GUEST_DEVICE)
+        .map_err(|e| report_error(format!("failed to open {} {}", SEV_GUEST_DEVICE, e)))?;
+    let req = SnpReportReq {
+        user_data,
+        vmpl: 0,
+        rsvd: [0; 28],
+    };
+    let mut resp = SnpReportResp {
+        data: [0; SNP_REPORT_RESP_SIZE],
+    };
+    let mut request = SnpGuestRequestIoctl {
+        msg_version: SNP_MSG_VERSION,
+        req_data: &req as *const SnpReportReq as u64,
+        resp_data: &mut resp as *mut SnpReportResp as u64,
+        exitinfo2: 0,
+    };
+    let result = unsafe { libc::ioctl(device.as_raw_fd(), SNP_GET_REPORT as _, &mut request) };
+    if result != 0 {
+        return Err(report_error(format!(
+            "SNP_GET_REPORT failed: {}, exitinfo2 {:#x}",
+            std::io::Error::last_os_error(),
+            request.exitinfo2
+        )));
+    }
+    let status = u32::from_le_bytes(resp.data[0..4].try_into().unwrap());
+    let size = u32::from_le_bytes(resp.data[4..8].try_into().unwrap()) as usize;
+    if status != 0 || SNP_REPORT_OFFSET + size > SNP_REPORT_RESP_SIZE {
+        return Err(report_error(format!("invalid report response, status {:#x} size {}", status, size)));
+    }
+    Ok(resp.data[SNP_REPORT_OFFSET..SNP_REPORT_OFFSET + size].to_vec())
+}
+
+async fn internal_get_report(report_data: ReportData) -> Result<String, Error> {
+    let report = get_snp_report(report_data.tdx_report_data())?;
+    info!("Successfully get the SEV-SNP report.");
+    let report = SevSnpReport {
+        report: hex::encode(report),
+        report_data: hex::encode(report_data.encode()),
+        vcek: String::new(),
+    };
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+impl TEEProvider for SevSnp {
+    fn get_report(
+        &self,
+        report_data: ReportData,
+    ) -> Pin<Box<dyn Future<Output = Result<String, Error>> + Send>> {
+        Box::pin(internal_get_report(report_data))
+    }
+
+    fn get_type(&self) -> Result<TEEType, Error> {
+        Ok(TEEType::SevSnp)
+    }
+}
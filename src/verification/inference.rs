@@ -0,0 +1,220 @@
+use crate::chains::contract::Contract;
+use crate::node::aizel::{VerificationCheck, VerifyInferenceResponse};
+use crate::node::aizel_server::AizelInference;
+use crate::node::config::{NetworkConfig, OUTPUT_BUCKET, REPORT_BUCKET};
+use crate::s3_minio::client::MinioClient;
+use common::error::Error;
+use common::tee::TEEType;
+use serde::Serialize;
+use std::path::PathBuf;
+use verifier::policy::{report_preimage, report_type};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Outcome of every check run on the result of a request.
+#[derive(Serialize, Debug, Default)]
+pub struct Summary {
+    pub network: String,
+    pub request_id: u64,
+    pub tee_type: Option<String>,
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Summary {
+    pub fn new(network: &str, request_id: u64) -> Self {
+        Summary {
+            network: network.to_string(),
+            request_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn check(&mut self, name: &str, status: Status, detail: String) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            status,
+            detail,
+        });
+    }
+
+    pub fn pass(&mut self, name: &str, detail: String) {
+        self.check(name, Status::Pass, detail)
+    }
+
+    pub fn fail(&mut self, name: &str, detail: String) {
+        self.check(name, Status::Fail, detail)
+    }
+
+    /// Records the verification of the report by the TEE verifier.
+    pub fn attestation(&mut self, tee_type: TEEType, result: Result<(), String>) {
+        match result {
+            Ok(_) => self.pass("attestation", format!("{} report verified", tee_type)),
+            Err(e) => self.fail("attestation", e),
+        }
+    }
+
+    /// Passed if at least one check ran and none failed.
+    pub fn finish(&mut self) {
+        self.passed = !self.checks.is_empty() && self.checks.iter().all(|c| c.status != Status::Fail);
+    }
+}
+
+impl From<Summary> for VerifyInferenceResponse {
+    fn from(summary: Summary) -> Self {
+        VerifyInferenceResponse {
+            passed: summary.passed,
+            tee_type: summary.tee_type.unwrap_or_default(),
+            checks: summary
+                .checks
+                .into_iter()
+                .map(|c| VerificationCheck {
+                    name: c.name,
+                    status: format!("{:?}", c.status).to_lowercase(),
+                    detail: c.detail,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Where the results of requests are read from.
+#[derive(Debug, Clone, Default)]
+pub struct InferenceSource {
+    // network configs as served by the config server
+    pub network_config: PathBuf,
+    // public data node the outputs and reports are downloaded from, named after their hash
+    pub data_node_url: Option<String>,
+    // local copies used instead of downloading them
+    pub output_file: Option<PathBuf>,
+    pub report_file: Option<PathBuf>,
+}
+
+/// Report of a request along with the nonce it has to be issued for.
+pub struct InferenceReport {
+    pub report: String,
+    pub tee_type: TEEType,
+    pub nonce: Option<String>,
+}
+
+impl InferenceSource {
+    fn network_config(&self, network: &str) -> Result<NetworkConfig, Error> {
+        let content = std::fs::read_to_string(&self.network_config).map_err(|e| Error::FileError {
+            path: self.network_config.clone(),
+            message: e.to_string(),
+        })?;
+        let configs: Vec<NetworkConfig> = serde_json::from_str(&content).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse network config {}", e),
+        })?;
+        configs
+            .into_iter()
+            .find(|c| c.network == network)
+            .ok_or(Error::NetworkConfigNotFoundError {
+                network: network.to_string(),
+            })
+    }
+
+    async fn fetch(&self, file: &Option<PathBuf>, bucket: &str, hash: &[u8; 32]) -> Result<String, Error> {
+        if let Some(path) = file {
+            return std::fs::read_to_string(path).map_err(|e| Error::FileError {
+                path: path.clone(),
+                message: e.to_string(),
+            });
+        }
+        let url = self.data_node_url.clone().ok_or(Error::InvalidArgumentError {
+            argument: "data_node_url".to_string(),
+            message: format!("required to download from {}", bucket),
+        })?;
+        MinioClient::try_new(url, None)?
+            .get_object(bucket, &format!("0x{}", hex::encode(hash)))
+            .await
+    }
+
+    /// Checks the output and report match the hashes submitted on chain and the report_data was
+    /// issued for the request. Returns the report to verify unless a check ruled it out.
+    /// `nonce` is used for reports that don't carry their report_data preimage, such a report fails
+    /// without it as nothing binds it to the request.
+    pub async fn check(
+        &self,
+        network: &str,
+        request_id: u64,
+        nonce: Option<String>,
+        summary: &mut Summary,
+    ) -> Result<Option<InferenceReport>, Error> {
+        let network_config = self.network_config(network)?;
+        let (output_hash, report_hash) = Contract::query_inference_result(request_id, &network_config).await?;
+        if output_hash == [0; 32] {
+            summary.fail("on-chain result", "no output submitted".to_string());
+            return Ok(None);
+        }
+        summary.pass(
+            "on-chain result",
+            format!("output 0x{} report 0x{}", hex::encode(output_hash), hex::encode(report_hash)),
+        );
+
+        let output = self.fetch(&self.output_file, OUTPUT_BUCKET, &output_hash).await?;
+        check_hash(summary, "output hash", &output, &output_hash);
+        let report = self.fetch(&self.report_file, REPORT_BUCKET, &report_hash).await?;
+        check_hash(summary, "report hash", &report, &report_hash);
+
+        let tee_type = report_type(&report);
+        summary.tee_type = Some(tee_type.to_string());
+        let nonce = match report_preimage(&report) {
+            Ok(Some(report_data)) => {
+                let mut mismatches = vec![];
+                if report_data.request_id != request_id {
+                    mismatches.push(format!("request id {}", report_data.request_id));
+                }
+                if report_data.network != network {
+                    mismatches.push(format!("network {}", report_data.network));
+                }
+                if report_data.output_hash != output_hash {
+                    mismatches.push(format!("output hash 0x{}", hex::encode(report_data.output_hash)));
+                }
+                if mismatches.is_empty() {
+                    summary.pass("report data", format!("node {}", hex::encode(&report_data.node_pk)));
+                } else {
+                    summary.fail("report data", format!("issued for {}", mismatches.join(", ")));
+                }
+                Some(report_data.nonce())
+            }
+            Ok(None) => {
+                match &nonce {
+                    Some(nonce) => summary.check("report data", Status::Skip, format!("checking the given nonce {}", nonce)),
+                    None => summary.fail("report data", "the report isn't bound to the request, no nonce given".to_string()),
+                }
+                nonce
+            }
+            Err(e) => {
+                summary.fail("report data", e.to_string());
+                return Ok(None);
+            }
+        };
+        Ok(Some(InferenceReport { report, tee_type, nonce }))
+    }
+}
+
+fn check_hash(summary: &mut Summary, name: &str, content: &str, expect: &[u8; 32]) {
+    let hash = AizelInference::hash(content).0;
+    if hash == *expect {
+        summary.pass(name, format!("0x{}", hex::encode(hash)));
+    } else {
+        summary.fail(
+            name,
+            format!("expect 0x{} get 0x{}", hex::encode(expect), hex::encode(hash)),
+        );
+    }
+}
@@ -0,0 +1,2 @@
+pub mod inference;
+pub mod server;
@@ -0,0 +1,427 @@
+use super::inference::{InferenceSource, Summary};
+use crate::node::aizel::verifier_service_server::VerifierService;
+use crate::node::aizel::{VerifyInferenceRequest, VerifyInferenceResponse, VerifyReportRequest, VerifyReportResponse};
+use crate::node::aizel_server::AizelInference;
+use common::error::Error;
+use base64::Engine;
+use common::tee::{verifier::TEEVerifier, TEEType};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tonic::{Request, Response, Status};
+use verifier::policy::{report_type, Policy};
+
+pub const DEFAULT_POLICY: &str = "default";
+pub const DEFAULT_RESULT_CACHE_CAPACITY: usize = 10_000;
+pub const DEFAULT_RESULT_CACHE_TTL_SECS: u64 = 600;
+// reports are a few KB, quotes with their collateral well below this
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Loads every YAML or JSON policy of `dir`, named after its file stem.
+pub fn load_policies<P: AsRef<Path>>(dir: P) -> Result<HashMap<String, Policy>, Error> {
+    let dir = dir.as_ref();
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::FileError {
+        path: dir.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    let mut policies = HashMap::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        if !["yml", "yaml", "json"].contains(&extension) {
+            continue;
+        }
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        info!("load policy {} from {:?}", name, path);
+        policies.insert(name, Policy::from_file(&path)?);
+    }
+    Ok(policies)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    report_hash: [u8; 32],
+    tee_type: TEEType,
+    nonce: Option<String>,
+    policy: String,
+}
+
+/// Results of the reports verified lately, the oldest is dropped once full. Results expire after
+/// the TTL, or once the report does.
+struct ResultCache {
+    results: HashMap<CacheKey, (Result<(), String>, Instant)>,
+    order: VecDeque<CacheKey>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl ResultCache {
+    fn get(&self, key: &CacheKey) -> Option<Result<(), String>> {
+        self.results
+            .get(key)
+            .filter(|(_, expires_at)| Instant::now() < *expires_at)
+            .map(|(result, _)| result.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, result: Result<(), String>, ttl: Duration) {
+        if ttl.is_zero() {
+            return;
+        }
+        if self.results.len() >= self.capacity && !self.results.contains_key(&key) {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+        let expires_at = Instant::now() + ttl.min(self.ttl);
+        if self.results.insert(key.clone(), (result, expires_at)).is_none() {
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// Time left until a GCP token expires, unbounded for reports without an expiry.
+fn report_ttl(report: &str, tee_type: TEEType) -> Duration {
+    if tee_type != TEEType::GCP {
+        return Duration::MAX;
+    }
+    let exp = report
+        .split('.')
+        .nth(1)
+        .and_then(|claims| base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(claims).ok())
+        .and_then(|claims| serde_json::from_slice::<serde_json::Value>(&claims).ok())
+        .and_then(|claims| claims.get("exp").and_then(|exp| exp.as_u64()));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    match exp {
+        Some(exp) => Duration::from_secs(exp.saturating_sub(now)),
+        None => Duration::ZERO,
+    }
+}
+
+/// Verifies reports for third parties over gRPC and JSON/HTTP with the named policies.
+pub struct VerifierServer {
+    policies: HashMap<String, Policy>,
+    source: InferenceSource,
+    // built once per policy and TEE so the verifiers keep their caches, e.g. the GCP signing keys
+    verifiers: Mutex<HashMap<(String, TEEType), Arc<dyn TEEVerifier>>>,
+    cache: Mutex<ResultCache>,
+}
+
+fn invalid_argument(argument: &str, message: String) -> Error {
+    Error::InvalidArgumentError {
+        argument: argument.to_string(),
+        message,
+    }
+}
+
+fn is_invalid_argument(e: &Error) -> bool {
+    matches!(e, Error::InvalidArgumentError { .. } | Error::NetworkConfigNotFoundError { .. })
+}
+
+fn to_status(e: Error) -> Status {
+    if is_invalid_argument(&e) {
+        Status::invalid_argument(e.to_string())
+    } else {
+        Status::internal(e.to_string())
+    }
+}
+
+impl VerifierServer {
+    pub fn new(policies: HashMap<String, Policy>, source: InferenceSource) -> Self {
+        Self::with_capacity(policies, source, DEFAULT_RESULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_capacity(policies: HashMap<String, Policy>, source: InferenceSource, capacity: usize) -> Self {
+        VerifierServer {
+            policies,
+            source,
+            verifiers: Mutex::new(HashMap::new()),
+            cache: Mutex::new(ResultCache {
+                results: HashMap::new(),
+                order: VecDeque::new(),
+                capacity,
+                ttl: Duration::from_secs(DEFAULT_RESULT_CACHE_TTL_SECS),
+            }),
+        }
+    }
+
+    async fn verifier(&self, policy: &str, tee_type: TEEType) -> Result<(Arc<dyn TEEVerifier>, bool), Error> {
+        let config = self
+            .policies
+            .get(policy)
+            .ok_or(invalid_argument("policy", format!("unknown policy {}", policy)))?;
+        let key = (policy.to_string(), tee_type);
+        if let Some(verifier) = self.verifiers.lock().unwrap().get(&key) {
+            return Ok((verifier.clone(), config.skip_verify_image_digest));
+        }
+        // built without the lock, it may fetch keys or certificates; the first one built is kept
+        let verifier: Arc<dyn TEEVerifier> = Arc::from(config.verifier(tee_type).await?);
+        let verifier = self.verifiers.lock().unwrap().entry(key).or_insert(verifier).clone();
+        Ok((verifier, config.skip_verify_image_digest))
+    }
+
+    /// Verifies `report`, from the cache if it was verified already with the same nonce and policy.
+    /// Only verification errors are cached, others like an unreachable issuer are returned.
+    async fn attest(&self, report: String, tee_type: TEEType, nonce: Option<String>, policy: &str) -> Result<(Result<(), String>, bool), Error> {
+        let ttl = report_ttl(&report, tee_type);
+        let key = CacheKey {
+            report_hash: AizelInference::hash(&report).0,
+            tee_type,
+            nonce: nonce.clone(),
+            policy: policy.to_string(),
+        };
+        if let Some(result) = self.cache.lock().unwrap().get(&key) {
+            return Ok((result, true));
+        }
+        let verified = match self.verifier(policy, tee_type).await {
+            Ok((verifier, skip_verify_image_digest)) => verifier.verify(report, nonce, skip_verify_image_digest).await,
+            Err(e) => Err(e),
+        };
+        let result = match verified {
+            Ok(_) => Ok(()),
+            Err(e @ Error::VerificationError { .. }) => Err(e.to_string()),
+            Err(e) => return Err(e),
+        };
+        self.cache.lock().unwrap().insert(key, result.clone(), ttl);
+        Ok((result, false))
+    }
+
+    pub async fn verify_report(&self, req: VerifyReportRequest) -> Result<VerifyReportResponse, Error> {
+        let tee_type = if req.tee_type.is_empty() {
+            report_type(&req.report)
+        } else {
+            req.tee_type.parse().map_err(|e| invalid_argument("tee_type", e))?
+        };
+        if tee_type == TEEType::Unkown {
+            return Err(invalid_argument("report", "unknown report format".to_string()));
+        }
+        let nonce = Some(req.nonce).filter(|n| !n.is_empty());
+        let policy = if req.policy.is_empty() { DEFAULT_POLICY } else { &req.policy };
+        let (result, cached) = self.attest(req.report, tee_type, nonce, policy).await?;
+        Ok(VerifyReportResponse {
+            verified: result.is_ok(),
+            tee_type: tee_type.to_string(),
+            error: result.err().unwrap_or_default(),
+            cached,
+        })
+    }
+
+    pub async fn verify_inference(&self, req: VerifyInferenceRequest) -> Result<VerifyInferenceResponse, Error> {
+        let policy = if req.policy.is_empty() { DEFAULT_POLICY } else { &req.policy };
+        if !self.policies.contains_key(policy) {
+            return Err(invalid_argument("policy", format!("unknown policy {}", policy)));
+        }
+        let nonce = Some(req.nonce).filter(|n| !n.is_empty());
+        let mut summary = Summary::new(&req.network, req.request_id);
+        match self.source.check(&req.network, req.request_id, nonce, &mut summary).await {
+            Ok(Some(report)) => {
+                let result = self
+                    .attest(report.report, report.tee_type, report.nonce, policy)
+                    .await
+                    .map(|(result, _)| result)
+                    .unwrap_or_else(|e| Err(e.to_string()));
+                summary.attestation(report.tee_type, result);
+            }
+            Ok(None) => {}
+            Err(e) => summary.fail("verification", e.to_string()),
+        }
+        summary.finish();
+        Ok(summary.into())
+    }
+
+    async fn handle_http(&self, req: hyper::Request<Incoming>) -> hyper::Response<Full<Bytes>> {
+        let path = req.uri().path().to_string();
+        if req.method() != hyper::Method::POST {
+            return json_response(hyper::StatusCode::METHOD_NOT_ALLOWED, error_body("only POST is supported"));
+        }
+        let body = match Limited::new(req.into_body(), MAX_BODY_BYTES).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) if e.downcast_ref::<LengthLimitError>().is_some() => {
+                return json_response(hyper::StatusCode::PAYLOAD_TOO_LARGE, error_body(&e.to_string()))
+            }
+            Err(e) => return json_response(hyper::StatusCode::BAD_REQUEST, error_body(&e.to_string())),
+        };
+        let result = match path.as_str() {
+            "/v1/verify_report" => match serde_json::from_slice::<VerifyReportRequest>(&body) {
+                Ok(req) => self.verify_report(req).await.map(|r| serde_json::to_string(&r).unwrap()),
+                Err(e) => Err(invalid_argument("body", e.to_string())),
+            },
+            "/v1/verify_inference" => match serde_json::from_slice::<VerifyInferenceRequest>(&body) {
+                Ok(req) => self.verify_inference(req).await.map(|r| serde_json::to_string(&r).unwrap()),
+                Err(e) => Err(invalid_argument("body", e.to_string())),
+            },
+            _ => return json_response(hyper::StatusCode::NOT_FOUND, error_body(&format!("no route {}", path))),
+        };
+        match result {
+            Ok(body) => json_response(hyper::StatusCode::OK, body),
+            Err(e) if is_invalid_argument(&e) => json_response(hyper::StatusCode::BAD_REQUEST, error_body(&e.to_string())),
+            Err(e) => json_response(hyper::StatusCode::INTERNAL_SERVER_ERROR, error_body(&e.to_string())),
+        }
+    }
+
+    /// Serves the JSON endpoints `/v1/verify_report` and `/v1/verify_inference`.
+    pub async fn serve_http(self: Arc<Self>, addr: SocketAddr) -> Result<(), Error> {
+        let listener = TcpListener::bind(addr).await.map_err(|e| Error::ServerError {
+            message: format!("failed to listen: {}", e),
+        })?;
+        loop {
+            let (stream, _) = listener.accept().await.map_err(|e| Error::ServerError {
+                message: format!("failed to accept: {}", e),
+            })?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle_http(req).await) }
+                });
+                if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                    warn!("failed to serve http connection {}", e);
+                }
+            });
+        }
+    }
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn json_response(status: hyper::StatusCode, body: String) -> hyper::Response<Full<Bytes>> {
+    hyper::Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+#[tonic::async_trait]
+impl VerifierService for Arc<VerifierServer> {
+    async fn verify_report(&self, request: Request<VerifyReportRequest>) -> Result<Response<VerifyReportResponse>, Status> {
+        VerifierServer::verify_report(self, request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(to_status)
+    }
+
+    async fn verify_inference(&self, request: Request<VerifyInferenceRequest>) -> Result<Response<VerifyInferenceResponse>, Status> {
+        VerifierServer::verify_inference(self, request.into_inner())
+            .await
+            .map(Response::new)
+            .map_err(to_status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::secret::Secret;
+    use crate::tee::mock::Mock;
+    use common::tee::report_data::ReportData;
+
+    fn server() -> VerifierServer {
+        let mut policies = HashMap::new();
+        policies.insert(DEFAULT_POLICY.to_string(), Policy::from_str("allow_mock: true").unwrap());
+        policies.insert("strict".to_string(), Policy::default());
+        VerifierServer::with_capacity(policies, InferenceSource::default(), 1)
+    }
+
+    #[tokio::test]
+    async fn test_verify_report() {
+        let secret = Secret::new();
+        let report_data = ReportData {
+            node_pk: secret.name.0.to_vec(),
+            network: "aizel".to_string(),
+            request_id: 1,
+            ..Default::default()
+        };
+        let report = Mock::new(secret.secret.clone()).report(&report_data);
+        let server = server();
+        let req = VerifyReportRequest {
+            report: report.clone(),
+            nonce: report_data.nonce(),
+            ..Default::default()
+        };
+
+        let resp = server.verify_report(req.clone()).await.unwrap();
+        assert!(resp.verified && !resp.cached);
+        assert_eq!(resp.tee_type, TEEType::Mock.to_string());
+        assert!(server.verify_report(req.clone()).await.unwrap().cached);
+
+        // replayed for another request
+        let other = ReportData {
+            request_id: 2,
+            ..report_data.clone()
+        };
+        let resp = server
+            .verify_report(VerifyReportRequest {
+                nonce: other.nonce(),
+                ..req.clone()
+            })
+            .await
+            .unwrap();
+        assert!(!resp.verified && !resp.error.is_empty());
+        // the cache only holds the last result
+        assert!(!server.verify_report(req.clone()).await.unwrap().cached);
+
+        let resp = server
+            .verify_report(VerifyReportRequest {
+                policy: "strict".to_string(),
+                ..req.clone()
+            })
+            .await
+            .unwrap();
+        assert!(!resp.verified);
+        assert!(server
+            .verify_report(VerifyReportRequest {
+                policy: "unknown".to_string(),
+                ..req
+            })
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_result_ttl() {
+        let encode = |claims: serde_json::Value| {
+            format!(
+                "header.{}.signature",
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string())
+            )
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let ttl = report_ttl(&encode(serde_json::json!({ "exp": now + 60 })), TEEType::GCP);
+        assert!(ttl <= Duration::from_secs(60) && ttl >= Duration::from_secs(59));
+        assert_eq!(report_ttl(&encode(serde_json::json!({ "exp": now - 1 })), TEEType::GCP), Duration::ZERO);
+        assert_eq!(report_ttl("not a token", TEEType::GCP), Duration::ZERO);
+        assert_eq!(report_ttl("{}", TEEType::Mock), Duration::MAX);
+
+        let mut cache = ResultCache {
+            results: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: 10,
+            ttl: Duration::from_secs(600),
+        };
+        let key = CacheKey {
+            report_hash: [0; 32],
+            tee_type: TEEType::GCP,
+            nonce: None,
+            policy: DEFAULT_POLICY.to_string(),
+        };
+        // expired tokens aren't cached
+        cache.insert(key.clone(), Ok(()), Duration::ZERO);
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), Ok(()), Duration::MAX);
+        assert!(cache.get(&key).is_some());
+        cache.results.get_mut(&key).unwrap().1 = Instant::now();
+        assert!(cache.get(&key).is_none());
+    }
+}
@@ -1,5 +1,8 @@
 use async_trait::async_trait;
+use super::report_data::{check_nonce, decode_report_data};
+use super::tdx_quote::{Quote, TdReportBody, TdxPolicy};
 use common::error::{Error, VerificationError};
+use common::tee::event_log::{replay, TdxReport, MODEL_RTMR_INDEX};
 use common::tee::{verifier::TEEVerifier, TEEType, TEEType::AliCloud};
 use intel_tee_quote_verification_rs::*;
 use log::{info, warn};
@@ -11,19 +14,98 @@ const SGX_DEBUG_FLAG: i32 = 1;
 #[cfg(not(debug_assertions))]
 const SGX_DEBUG_FLAG: i32 = 0;
 
-#[derive(Debug)]
-pub struct AliCloudVerifier {}
+/// Verifies TDX quotes with DCAP, then the TD report against the policy, the model event log
+/// against RTMR3 and the report_data against the preimage sent with the quote.
+#[derive(Debug, Default)]
+pub struct AliCloudVerifier {
+    policy: TdxPolicy,
+}
+
+fn tdx_error(error: VerificationError) -> Error {
+    Error::VerificationError {
+        teetype: AliCloud,
+        error,
+    }
+}
+
+impl AliCloudVerifier {
+    pub fn new(policy: TdxPolicy) -> Self {
+        AliCloudVerifier { policy }
+    }
+
+    /// Checks of the parsed quote, everything but its signature.
+    pub fn check_quote(&self, quote: &Quote, report: &TdxReport, skip_verify_image_digest: bool) -> Result<(), Error> {
+        self.policy
+            .check(&quote.body, skip_verify_image_digest)
+            .map_err(tdx_error)?;
+        verify_event_log(&quote.body, report)?;
+        verify_report_data(&quote.body, report, self.policy.allow_unbound_reports)
+    }
+}
 
-impl AliCloudVerifier {}
+/// Replays the event log and checks it ends up at the RTMR the quote was signed with.
+fn verify_event_log(body: &TdReportBody, report: &TdxReport) -> Result<(), Error> {
+    let rtmr = body.rtmrs[MODEL_RTMR_INDEX as usize];
+    let replayed = replay(&report.event_log, MODEL_RTMR_INDEX).map_err(tdx_error)?;
+    if replayed != rtmr {
+        return Err(tdx_error(VerificationError::GoldenValueMismatchError {
+            value: format!("rtmr{}", MODEL_RTMR_INDEX),
+            expect: hex::encode(replayed),
+            get: hex::encode(rtmr),
+        }));
+    }
+    for measurement in report.event_log.iter().filter_map(|e| e.measurement()) {
+        info!(
+            "model {} ({}) of network {} loaded, sha256 {}",
+            measurement.model_id, measurement.name, measurement.network, measurement.digest
+        );
+    }
+    Ok(())
+}
+
+/// Checks the report_data of the quote commits to the preimage sent along with it. Quotes without
+/// a preimage are only accepted from older nodes if the policy allows unbound reports.
+fn verify_report_data(body: &TdReportBody, report: &TdxReport, allow_unbound_reports: bool) -> Result<(), Error> {
+    if report.report_data.is_empty() {
+        if !allow_unbound_reports {
+            return Err(tdx_error(VerificationError::ReportDataError {
+                message: "report carries no report_data preimage".to_string(),
+            }));
+        }
+        warn!("report carries no report_data preimage, it isn't bound to a request");
+        return Ok(());
+    }
+    let preimage = hex::decode(&report.report_data).map_err(|_| tdx_error(VerificationError::DecodeError))?;
+    let decoded = decode_report_data(&body.report_data, &preimage).map_err(tdx_error)?;
+    info!(
+        "report of node {} for request {} of network {}, model {}",
+        hex::encode(&decoded.node_pk), decoded.request_id, decoded.network, decoded.model_id
+    );
+    Ok(())
+}
+
+/// Reports are a JSON `TdxReport`, those of older nodes the bare hex quote. A report that looks like
+/// JSON but doesn't parse is rejected rather than read as a quote.
+fn parse_report(report: String) -> Result<TdxReport, Error> {
+    if report.trim_start().starts_with('{') {
+        return serde_json::from_str(&report).map_err(|_| tdx_error(VerificationError::DecodeError));
+    }
+    Ok(TdxReport {
+        quote: report,
+        event_log: vec![],
+        report_data: String::new(),
+    })
+}
 
 #[async_trait]
 impl TEEVerifier for AliCloudVerifier {
-    async fn verify(&self, quote: String, skip_verify_image_digest: bool) -> Result<bool, Error> {
-        let quote = hex::decode(quote).map_err(|e| Error::VerificationError {
-            teetype: AliCloud,
-            error: VerificationError::DecodeError,
-        })?;
+    async fn verify(&self, report: String, nonce: Option<String>, skip_verify_image_digest: bool) -> Result<bool, Error> {
+        let report = parse_report(report)?;
+        let quote = hex::decode(&report.quote).map_err(|_| tdx_error(VerificationError::DecodeError))?;
+        let parsed = Quote::parse(&quote).map_err(tdx_error)?;
         ecdsa_quote_verification(&quote, false)?;
+        self.check_quote(&parsed, &report, skip_verify_image_digest)?;
+        check_nonce(&nonce, &parsed.body.report_data[..32]).map_err(tdx_error)?;
         return Ok(true);
     }
 
@@ -216,3 +298,36 @@ fn ecdsa_quote_verification(quote: &[u8], use_qve: bool) -> Result<(), Error> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unbound_report() {
+        let quote = hex::decode(include_str!("../tests/fixtures/tdx_quote_v4.hex").trim()).unwrap();
+        let quote = Quote::parse(&quote).unwrap();
+        let report = TdxReport {
+            quote: String::new(),
+            event_log: vec![],
+            report_data: String::new(),
+        };
+        assert!(verify_report_data(&quote.body, &report, false).is_err());
+        assert!(verify_report_data(&quote.body, &report, true).is_ok());
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = parse_report(r#"{"quote": "0400", "event_log": [], "report_data": "01"}"#.to_string()).unwrap();
+        assert_eq!(report.quote, "0400");
+        assert_eq!(report.report_data, "01");
+        let legacy = parse_report("0400".to_string()).unwrap();
+        assert_eq!(legacy.quote, "0400");
+        assert!(legacy.report_data.is_empty());
+        // a truncated report isn't mistaken for a bare quote
+        assert!(matches!(
+            parse_report(r#" {"quote": "0400", "event_"#.to_string()),
+            Err(Error::VerificationError { error: VerificationError::DecodeError, .. })
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 pub const EXPECTED_ISSUER: &str = "https://confidentialcomputing.googleapis.com";
 pub const GCP_WELL_KNOWN_URL_PATH: &str = "/.well-known/openid-configuration";
@@ -8,6 +8,8 @@ pub struct ContainerClaims {
     pub image_reference: String,
     pub image_digest: String,
     pub args: Vec<String>,
+    #[serde(default)]
+    pub restart_policy: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +28,24 @@ pub struct Claims {
     pub swname: String,
     pub swversion: Vec<String>,
     pub secboot: bool,
+    #[serde(default)]
+    pub dbgstat: String,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub eat_nonce: Vec<String>,
+}
+
+/// `eat_nonce` is a string for a single nonce and an array otherwise.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(nonce) => vec![nonce],
+        OneOrMany::Many(nonces) => nonces,
+    })
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
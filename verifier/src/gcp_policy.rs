@@ -0,0 +1,160 @@
+use super::gcp_claim::Claims;
+use common::error::Error;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// `dbgstat` of a production Confidential Space image.
+pub const DBGSTAT_DISABLED: &str = "disabled-since-boot";
+
+/// Golden values for the claims of a Confidential Space token, loaded from YAML or JSON.
+/// Unset claims aren't checked, allow-lists accept any of their values.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcpPolicy {
+    #[serde(default)]
+    pub image_reference: Option<String>,
+    // several digests are allowed while a new image rolls out
+    #[serde(default)]
+    pub image_digests: Option<Vec<String>>,
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
+    #[serde(default)]
+    pub restart_policy: Option<String>,
+    #[serde(default)]
+    pub dbgstat: Option<String>,
+    #[serde(default)]
+    pub secboot: Option<bool>,
+    #[serde(default)]
+    pub hwmodels: Option<Vec<String>>,
+    #[serde(default)]
+    pub swnames: Option<Vec<String>>,
+    // every version the token reports has to be allowed
+    #[serde(default)]
+    pub swversions: Option<Vec<String>>,
+}
+
+fn violation(claim: &str, expect: impl std::fmt::Debug, get: impl std::fmt::Debug) -> String {
+    format!("{} expect: {:?} get: {:?}", claim, expect, get)
+}
+
+fn check_allowed(violations: &mut Vec<String>, claim: &str, allowed: &Option<Vec<String>>, get: &String) {
+    if let Some(allowed) = allowed {
+        if !allowed.contains(get) {
+            violations.push(violation(claim, allowed, get));
+        }
+    }
+}
+
+fn check_equal<T: PartialEq + std::fmt::Debug>(violations: &mut Vec<String>, claim: &str, expect: &Option<T>, get: &T) {
+    if let Some(expect) = expect {
+        if expect != get {
+            violations.push(violation(claim, expect, get));
+        }
+    }
+}
+
+impl GcpPolicy {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        Self::from_str(&content)
+    }
+
+    /// Parses a YAML policy, JSON being a subset of YAML is accepted as well.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(content: &str) -> Result<Self, Error> {
+        serde_yaml::from_str(content).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse gcp policy {}", e),
+        })
+    }
+
+    /// Checks the claims against the policy and returns every violated claim.
+    /// `skip_verify_image_digest` skips the image digests only.
+    pub fn check(&self, claims: &Claims, skip_verify_image_digest: bool) -> Result<(), Vec<String>> {
+        let container = &claims.submods.container;
+        let mut violations = vec![];
+        check_equal(&mut violations, "image_reference", &self.image_reference, &container.image_reference);
+        if !skip_verify_image_digest {
+            check_allowed(&mut violations, "image_digest", &self.image_digests, &container.image_digest);
+        }
+        check_equal(&mut violations, "args", &self.args, &container.args);
+        check_equal(&mut violations, "restart_policy", &self.restart_policy, &container.restart_policy);
+        check_equal(&mut violations, "dbgstat", &self.dbgstat, &claims.dbgstat);
+        check_equal(&mut violations, "secboot", &self.secboot, &claims.secboot);
+        check_allowed(&mut violations, "hwmodel", &self.hwmodels, &claims.hwmodel);
+        check_allowed(&mut violations, "swname", &self.swnames, &claims.swname);
+        for swversion in claims.swversion.iter() {
+            check_allowed(&mut violations, "swversion", &self.swversions, swversion);
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcp_claim::{ContainerClaims, Submods};
+
+    fn claims() -> Claims {
+        Claims {
+            aud: "https://sts.googleapis.com".to_string(),
+            iss: "https://confidentialcomputing.googleapis.com".to_string(),
+            sub: "inference-demo".to_string(),
+            exp: 0,
+            submods: Submods {
+                container: ContainerClaims {
+                    image_reference: "asia-docker.pkg.dev/aizel/aizel_inference:0.1.0".to_string(),
+                    image_digest: "sha256:01".to_string(),
+                    args: vec!["/bin/sh".to_string(), "-c".to_string(), "/bin/bash bootstrap.sh".to_string()],
+                    restart_policy: "Never".to_string(),
+                },
+            },
+            hwmodel: "GCP_AMD_SEV".to_string(),
+            swname: "CONFIDENTIAL_SPACE".to_string(),
+            swversion: vec!["240402".to_string()],
+            secboot: true,
+            dbgstat: "enabled".to_string(),
+            eat_nonce: vec![],
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = GcpPolicy::from_str(
+            r#"
+image_reference: asia-docker.pkg.dev/aizel/aizel_inference:0.1.0
+image_digests: ["sha256:00", "sha256:01"]
+args: ["/bin/sh", "-c", "/bin/bash bootstrap.sh"]
+restart_policy: Never
+secboot: true
+hwmodels: [GCP_AMD_SEV, GCP_INTEL_TDX]
+swnames: [CONFIDENTIAL_SPACE]
+swversions: ["240402"]
+"#,
+        )
+        .unwrap();
+        assert!(policy.check(&claims(), false).is_ok());
+
+        let policy = GcpPolicy {
+            image_digests: Some(vec!["sha256:02".to_string()]),
+            dbgstat: Some(DBGSTAT_DISABLED.to_string()),
+            ..policy
+        };
+        let violations = policy.check(&claims(), false).unwrap_err();
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].starts_with("image_digest"));
+        assert!(violations[1].starts_with("dbgstat"));
+        assert_eq!(policy.check(&claims(), true).unwrap_err().len(), 1);
+
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(GcpPolicy::from_str(&json).unwrap(), policy);
+        assert!(GcpPolicy::from_str("secure_boot: true").is_err());
+    }
+}
@@ -1,18 +1,42 @@
 use super::gcp_claim::*;
+use super::gcp_policy::GcpPolicy;
 use async_trait::async_trait;
 use common::error::{Error, VerificationError};
 use common::tee::{verifier::TEEVerifier, TEEType};
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Header, Validation};
-use log::error;
+use log::{error, info, warn};
 use reqwest::Client;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use url::Url;
+
+pub const DEFAULT_JWKS_TTL_SECS: u64 = 3600;
+// tokens with unknown kids can't make the verifier hammer the issuer
+pub const DEFAULT_KID_REFRESH_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Default)]
+struct KeyCache {
+    keys: Option<KeySets>,
+    fetched_at: Option<Instant>,
+    // last refresh triggered by an unknown kid
+    kid_refreshed_at: Option<Instant>,
+}
+
 /// This attestation verifier only works for GCP confidential space
 #[derive(Debug)]
 pub struct GcpVerifier {
-    image_reference: String,
-    image_digest: String,
+    policy: GcpPolicy,
     audience: String,
     issuer: String,
+    // where the OpenID configuration is served, the issuer unless tests stand in for it
+    base_url: String,
+    // signing keys of the issuer, refreshed once stale or when a token names an unknown kid
+    keys: Mutex<KeyCache>,
+    jwks_ttl: Duration,
+    kid_refresh_interval: Duration,
+    // only the preloaded keys are used, nothing is fetched
+    offline: bool,
 }
 
 impl GcpVerifier {
@@ -25,15 +49,109 @@ impl GcpVerifier {
         issuer: String,
     ) -> Self {
         GcpVerifier {
-            image_reference,
-            image_digest,
+            policy: GcpPolicy {
+                image_reference: Some(image_reference),
+                image_digests: Some(vec![image_digest]),
+                ..Default::default()
+            },
             audience,
             issuer,
+            base_url: EXPECTED_ISSUER.to_string(),
+            keys: Mutex::new(KeyCache::default()),
+            jwks_ttl: Duration::from_secs(DEFAULT_JWKS_TTL_SECS),
+            kid_refresh_interval: Duration::from_secs(DEFAULT_KID_REFRESH_INTERVAL_SECS),
+            offline: false,
         }
     }
 
+    /// Checks the claims of tokens against `policy` instead of the image golden values.
+    pub fn with_policy(mut self, policy: GcpPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Fetches the OpenID configuration from `base_url` instead of the issuer.
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn with_jwks_ttl(mut self, ttl: Duration) -> Self {
+        self.jwks_ttl = ttl;
+        self
+    }
+
+    /// Minimum time between the refreshes triggered by tokens naming an unknown kid.
+    pub fn with_kid_refresh_interval(mut self, interval: Duration) -> Self {
+        self.kid_refresh_interval = interval;
+        self
+    }
+
+    /// Verifies tokens with `keys` only, for air-gapped verification.
+    pub fn with_jwks(self, keys: KeySets) -> Self {
+        *self.keys.lock().unwrap() = KeyCache {
+            keys: Some(keys),
+            fetched_at: Some(Instant::now()),
+            kid_refreshed_at: None,
+        };
+        GcpVerifier {
+            offline: true,
+            ..self
+        }
+    }
+
+    /// Verifies tokens with the keys of a JWKS file only, for air-gapped verification.
+    pub fn with_jwks_file<P: AsRef<Path>>(self, path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let jwks = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let keys: KeySets = serde_json::from_str(&jwks).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse jwks {}", e),
+        })?;
+        Ok(self.with_jwks(keys))
+    }
+
+    /// Key `kid` of the issuer, from the cache unless it is stale or doesn't know the key. Unknown
+    /// kids refresh the cache at most once per `kid_refresh_interval`.
+    async fn get_key(&self, kid: String) -> Result<JsonWebKeySet, Error> {
+        {
+            let mut cache = self.keys.lock().unwrap();
+            let fresh = cache.fetched_at.map(|t| t.elapsed() < self.jwks_ttl).unwrap_or(false);
+            let cached = match &cache.keys {
+                Some(keys) if fresh || self.offline => Some(self.find_jwt_key_set(keys, kid.clone())),
+                _ => None,
+            };
+            match cached {
+                Some(Ok(key)) => return Ok(key),
+                Some(Err(e)) if self.offline => return Err(e),
+                Some(Err(e)) => {
+                    let recent = cache
+                        .kid_refreshed_at
+                        .map(|t| t.elapsed() < self.kid_refresh_interval)
+                        .unwrap_or(false);
+                    if recent {
+                        warn!("kid {} unknown, signing keys of {} were refreshed recently", kid, self.issuer);
+                        return Err(e);
+                    }
+                    cache.kid_refreshed_at = Some(Instant::now());
+                }
+                None => {}
+            }
+        }
+        let openid_configuration = self.get_openid_configuration().await?;
+        let keys = self.get_json_web_key_sets(openid_configuration.jwks_uri).await?;
+        info!("fetched {} signing keys of {}", keys.keys.len(), self.issuer);
+        let key = self.find_jwt_key_set(&keys, kid);
+        let mut cache = self.keys.lock().unwrap();
+        cache.keys = Some(keys);
+        cache.fetched_at = Some(Instant::now());
+        key
+    }
+
     async fn get_openid_configuration(&self) -> Result<OpenIDConfiguration, Error> {
-        let url_str = format!("{}{}", EXPECTED_ISSUER, GCP_WELL_KNOWN_URL_PATH);
+        let url_str = format!("{}{}", self.base_url, GCP_WELL_KNOWN_URL_PATH);
         let url = Url::parse(&url_str).unwrap();
         let client = Client::builder().build().unwrap();
         match client.get(url.clone()).send().await {
@@ -102,7 +220,7 @@ impl GcpVerifier {
 
 #[async_trait]
 impl TEEVerifier for GcpVerifier {
-    async fn verify(&self, report: String, skip_verify_image_digest: bool) -> Result<bool, Error> {
+    async fn verify(&self, report: String, nonce: Option<String>, skip_verify_image_digest: bool) -> Result<bool, Error> {
         let header: Header = decode_header(&report).map_err(|e| Error::VerificationError {
             teetype: TEEType::GCP,
             error: VerificationError::DecodeError,
@@ -116,11 +234,7 @@ impl TEEVerifier for GcpVerifier {
             });
         }
         let kid = header.kid.unwrap().clone();
-        let openid_configuration = self.get_openid_configuration().await?;
-        let key_sets: KeySets = self
-            .get_json_web_key_sets(openid_configuration.jwks_uri)
-            .await?;
-        let key_set: JsonWebKeySet = self.find_jwt_key_set(&key_sets, kid)?;
+        let key_set: JsonWebKeySet = self.get_key(kid).await?;
         let mut validation = Validation::new(Algorithm::RS256);
         validation.set_audience(&[self.audience.clone()]);
         validation.set_issuer(&[self.issuer.clone()]);
@@ -136,28 +250,26 @@ impl TEEVerifier for GcpVerifier {
             },
         })?;
 
-        if claims.claims.submods.container.image_reference != self.image_reference {
+        if let Err(violations) = self.policy.check(&claims.claims, skip_verify_image_digest) {
             return Err(Error::VerificationError {
                 teetype: TEEType::GCP,
-                error: VerificationError::GoldenValueMismatchError {
-                    value: "image_reference".to_string(),
-                    expect: self.image_reference.clone(),
-                    get: claims.claims.submods.container.image_reference.clone(),
-                },
+                error: VerificationError::PolicyViolationError { violations },
             });
         }
 
-        if !skip_verify_image_digest {
-            if claims.claims.submods.container.image_digest != self.image_digest {
+        match nonce {
+            Some(nonce) if !claims.claims.eat_nonce.iter().any(|n| n.to_lowercase() == nonce.to_lowercase()) => {
                 return Err(Error::VerificationError {
                     teetype: TEEType::GCP,
                     error: VerificationError::GoldenValueMismatchError {
-                        value: "image_digest".to_string(),
-                        expect: self.image_digest.clone(),
-                        get: claims.claims.submods.container.image_digest.clone(),
+                        value: "eat_nonce".to_string(),
+                        expect: nonce,
+                        get: claims.claims.eat_nonce.join(","),
                     },
                 });
             }
+            Some(_) => {}
+            None => warn!("eat_nonce {:?} of the token not checked", claims.claims.eat_nonce),
         }
 
         return Ok(true);
@@ -167,3 +279,166 @@ impl TEEVerifier for GcpVerifier {
         Ok(TEEType::GCP)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{encode, EncodingKey};
+    use openssl::rsa::Rsa;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const IMAGE_REFERENCE: &str = "asia-docker.pkg.dev/aizel/aizel_inference:0.1.0";
+    const AUDIENCE: &str = "http://aizel.com";
+    const NONCE: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    struct SigningKey {
+        kid: String,
+        pem: Vec<u8>,
+        jwk: JsonWebKeySet,
+    }
+
+    fn signing_key(kid: &str) -> SigningKey {
+        let rsa = Rsa::generate(2048).unwrap();
+        SigningKey {
+            kid: kid.to_string(),
+            pem: rsa.private_key_to_pem().unwrap(),
+            jwk: JsonWebKeySet {
+                alg: "RS256".to_string(),
+                kty: "RSA".to_string(),
+                n: URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+                usage: "sig".to_string(),
+                kid: kid.to_string(),
+                e: URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+            },
+        }
+    }
+
+    fn token(key: &SigningKey) -> String {
+        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 600;
+        let claims = serde_json::json!({
+            "aud": AUDIENCE,
+            "iss": EXPECTED_ISSUER,
+            "sub": "inference-node",
+            "exp": exp,
+            "hwmodel": "GCP_AMD_SEV",
+            "swname": "CONFIDENTIAL_SPACE",
+            "swversion": ["240402"],
+            "secboot": true,
+            "eat_nonce": NONCE,
+            "submods": {"container": {"image_reference": IMAGE_REFERENCE, "image_digest": "sha256:00", "args": []}},
+        });
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(key.kid.clone());
+        encode(&header, &claims, &EncodingKey::from_rsa_pem(&key.pem).unwrap()).unwrap()
+    }
+
+    /// Serves the OpenID configuration and the current JWKS, counting the JWKS requests.
+    async fn stand_in_issuer(jwks: Arc<Mutex<KeySets>>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let configuration = serde_json::json!({"issuer": EXPECTED_ISSUER, "jwks_uri": format!("{}/jwks", base_url)}).to_string();
+        let counter = fetches.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                let body = if String::from_utf8_lossy(&buf[..n]).starts_with(&format!("GET {}", GCP_WELL_KNOWN_URL_PATH)) {
+                    configuration.clone()
+                } else {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    serde_json::to_string(&*jwks.lock().unwrap()).unwrap()
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        (base_url, fetches)
+    }
+
+    fn verifier() -> GcpVerifier {
+        GcpVerifier::new(
+            IMAGE_REFERENCE.to_string(),
+            String::new(),
+            AUDIENCE.to_string(),
+            EXPECTED_ISSUER.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_jwks_cache() {
+        let first = signing_key("first");
+        let jwks = Arc::new(Mutex::new(KeySets { keys: vec![first.jwk.clone()] }));
+        let (base_url, fetches) = stand_in_issuer(jwks.clone()).await;
+        let verifier = verifier()
+            .with_base_url(base_url)
+            .with_kid_refresh_interval(Duration::from_secs(1));
+        let second = signing_key("second");
+        let third = signing_key("third");
+
+        assert!(verifier.verify(token(&first), None, true).await.unwrap());
+        assert!(verifier.verify(token(&first), None, true).await.unwrap());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // the issuer rotated its key, the unknown kid triggers a refresh
+        jwks.lock().unwrap().keys.push(second.jwk.clone());
+        assert!(verifier.verify(token(&second), Some(NONCE.to_string()), true).await.unwrap());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // unknown kids refresh at most once per interval
+        jwks.lock().unwrap().keys.push(third.jwk.clone());
+        assert!(matches!(
+            verifier.verify(token(&third), None, true).await,
+            Err(Error::VerificationError {
+                error: VerificationError::KidNotFoundError { .. },
+                ..
+            })
+        ));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(verifier.verify(token(&third), None, true).await.unwrap());
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_offline_jwks() {
+        let key = signing_key("offline");
+        let path = std::env::temp_dir().join(format!("aizel_jwks_{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_string(&KeySets { keys: vec![key.jwk.clone()] }).unwrap()).unwrap();
+        // nothing listens there, any fetch would fail
+        let verifier = verifier()
+            .with_base_url("http://127.0.0.1:1".to_string())
+            .with_jwks_ttl(Duration::ZERO)
+            .with_jwks_file(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(verifier.verify(token(&key), Some(NONCE.to_string()), true).await.unwrap());
+        // a valid token replayed for another nonce
+        assert!(matches!(
+            verifier.verify(token(&key), Some("00".repeat(32)), true).await,
+            Err(Error::VerificationError {
+                error: VerificationError::GoldenValueMismatchError { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            verifier.verify(token(&signing_key("unknown")), None, true).await,
+            Err(Error::VerificationError {
+                error: VerificationError::KidNotFoundError { .. },
+                ..
+            })
+        ));
+    }
+}
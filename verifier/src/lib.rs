@@ -1,48 +1,13 @@
-use common::error::Error;
-use common::tee::TEEType;
-use reqwest::header::HeaderMap;
+pub use common::tee::detect::get_current_tee_type;
 pub mod alicloud_verifier;
 pub mod gcp_claim;
+pub mod gcp_policy;
 pub mod gcp_verifier;
-
-pub async fn get_current_tee_type() -> Result<TEEType, Error> {
-    // Try GCP
-    {
-        let mut headers = HeaderMap::new();
-        headers.insert("Metadata-Flavor", "Google".parse().unwrap());
-        let client = reqwest::Client::new();
-        let response = client
-            .get("http://metadata.google.internal/computeMetadata/v1/instance/")
-            .headers(headers)
-            .send()
-            .await
-            .map_err(|e| Error::UnkownTEETypeERROR {
-                message: e.to_string(),
-            })?;
-        if response.status().is_success() {
-            return Ok(TEEType::GCP);
-        }
-    }
-
-    // Try AliCloud
-    {
-        let client = reqwest::Client::new();
-        let response = client
-            .get("http://100.100.100.200/latest/meta-data")
-            .send()
-            .await
-            .map_err(|e| Error::UnkownTEETypeERROR {
-                message: e.to_string(),
-            })?;
-        if response.status().is_success() {
-            return Ok(TEEType::AliCloud);
-        }
-    }
-
-    return Err(Error::UnkownTEETypeERROR {
-        message: "unkown tee type".to_string(),
-    });
-}
+pub mod mock_verifier;
+pub mod policy;
+pub mod report_data;
+pub mod sev_snp_verifier;
+pub mod tdx_quote;
 
 #[cfg(test)]
 mod tests {
@@ -65,6 +30,6 @@ mod tests {
             issuer.to_string(),
         );
 
-        gcp_verifier.verify(token.to_string(), true).await.unwrap();
+        gcp_verifier.verify(token.to_string(), None, true).await.unwrap();
     }
 }
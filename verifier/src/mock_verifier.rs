@@ -0,0 +1,95 @@
+use super::report_data::{check_nonce, decode};
+use async_trait::async_trait;
+use common::error::{Error, VerificationError};
+use common::tee::mock::MockReport;
+use common::tee::{verifier::TEEVerifier, TEEType, TEEType::Mock};
+use log::{info, warn};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+
+/// Verifies reports of nodes running the mock TEE provider: the report_data has to be signed by
+/// the node key it names. This only proves which node produced an output, use it for local runs.
+#[derive(Debug)]
+pub struct MockVerifier {}
+
+fn mock_error(error: VerificationError) -> Error {
+    Error::VerificationError { teetype: Mock, error }
+}
+
+#[async_trait]
+impl TEEVerifier for MockVerifier {
+    async fn verify(&self, report: String, nonce: Option<String>, _skip_verify_image_digest: bool) -> Result<bool, Error> {
+        let report: MockReport =
+            serde_json::from_str(&report).map_err(|_| mock_error(VerificationError::DecodeError))?;
+        let preimage = hex::decode(&report.report_data).map_err(|_| mock_error(VerificationError::DecodeError))?;
+        let report_data = decode(&preimage).map_err(mock_error)?;
+        let signature = hex::decode(&report.signature)
+            .ok()
+            .and_then(|s| Signature::from_compact(&s).ok())
+            .ok_or(mock_error(VerificationError::DecodeError))?;
+        let key = PublicKey::from_slice(&report_data.node_pk).map_err(|_| mock_error(VerificationError::DecodeError))?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(&Message::from_digest(report_data.digest()), &signature, &key)
+            .map_err(|e| {
+                mock_error(VerificationError::ValidateTokenError {
+                    message: format!("invalid signature {}", e),
+                })
+            })?;
+        check_nonce(&nonce, &report_data.digest()).map_err(mock_error)?;
+        warn!("mock report, no TEE attested the node");
+        info!(
+            "report of node {} for request {} of network {}, model {}",
+            hex::encode(&report_data.node_pk), report_data.request_id, report_data.network, report_data.model_id
+        );
+        Ok(true)
+    }
+
+    fn get_type(&self) -> Result<TEEType, Error> {
+        Ok(TEEType::Mock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tee::report_data::ReportData;
+    use secp256k1::SecretKey;
+
+    fn report(secret: &SecretKey, report_data: &ReportData) -> String {
+        let signature = Secp256k1::new().sign_ecdsa(&Message::from_digest(report_data.digest()), secret);
+        serde_json::to_string(&MockReport {
+            report_data: hex::encode(report_data.encode()),
+            signature: hex::encode(signature.serialize_compact()),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_mock_report() {
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        let node_pk = PublicKey::from_secret_key(&Secp256k1::new(), &secret).serialize().to_vec();
+        let report_data = ReportData {
+            node_pk,
+            network: "aizel".to_string(),
+            request_id: 1,
+            ..Default::default()
+        };
+        let verifier = MockVerifier {};
+        assert!(verifier
+            .verify(report(&secret, &report_data), Some(report_data.nonce()), false)
+            .await
+            .unwrap());
+        // a valid report replayed for another request
+        let other_request = ReportData {
+            request_id: 2,
+            ..report_data.clone()
+        };
+        assert!(verifier
+            .verify(report(&secret, &report_data), Some(other_request.nonce()), false)
+            .await
+            .is_err());
+
+        // signed by another key than the one in the report_data
+        let other = SecretKey::from_slice(&[8; 32]).unwrap();
+        assert!(verifier.verify(report(&other, &report_data), None, false).await.is_err());
+    }
+}
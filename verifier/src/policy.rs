@@ -0,0 +1,223 @@
+use super::alicloud_verifier::AliCloudVerifier;
+use super::gcp_claim::EXPECTED_ISSUER;
+use super::gcp_policy::GcpPolicy;
+use super::gcp_verifier::GcpVerifier;
+use super::mock_verifier::MockVerifier;
+use super::report_data::decode;
+use super::sev_snp_verifier::SevSnpVerifier;
+use super::tdx_quote::TdxPolicy;
+use common::error::{Error, VerificationError};
+use common::tee::event_log::TdxReport;
+use common::tee::mock::MockReport;
+use common::tee::report_data::ReportData;
+use common::tee::sev_snp::SevSnpReport;
+use common::tee::{verifier::TEEVerifier, TEEType};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+fn default_issuer() -> String {
+    EXPECTED_ISSUER.to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcpSection {
+    pub audience: String,
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    #[serde(default)]
+    pub claims: GcpPolicy,
+    // signing keys of the issuer, to verify tokens without reaching it
+    #[serde(default)]
+    pub jwks_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TdxSection {
+    #[serde(default)]
+    pub mr_td: Option<String>,
+    #[serde(default)]
+    pub rtmrs: [Option<String>; 4],
+    // quotes of older nodes without the report_data preimage, off unless set
+    #[serde(default)]
+    pub allow_unbound_reports: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SevSnpSection {
+    // processor family at the AMD key distribution service, e.g. Milan or Genoa
+    pub product: String,
+    // hex sha256 fingerprint of the DER encoded ARK of the product, pinned as the root of trust
+    pub ark_sha256: String,
+    #[serde(default)]
+    pub measurement: Option<String>,
+}
+
+/// What is accepted from each TEE, loaded from YAML or JSON. Reports of a TEE without a section
+/// are rejected, mock reports only when `allow_mock` is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    pub skip_verify_image_digest: bool,
+    #[serde(default)]
+    pub allow_mock: bool,
+    #[serde(default)]
+    pub gcp: Option<GcpSection>,
+    #[serde(default)]
+    pub tdx: Option<TdxSection>,
+    #[serde(default)]
+    pub sev_snp: Option<SevSnpSection>,
+}
+
+impl Policy {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| Error::FileError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        Self::from_str(&content)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(content: &str) -> Result<Self, Error> {
+        serde_yaml::from_str(content).map_err(|e| Error::SerDeError {
+            message: format!("failed to parse policy {}", e),
+        })
+    }
+
+    /// Verifier of `tee_type` reports configured with the policy.
+    pub async fn verifier(&self, tee_type: TEEType) -> Result<Box<dyn TEEVerifier>, Error> {
+        let missing = || Error::VerificationError {
+            teetype: tee_type,
+            error: VerificationError::PolicyViolationError {
+                violations: vec![format!("{} reports are not accepted", tee_type)],
+            },
+        };
+        match tee_type {
+            TEEType::GCP => {
+                let gcp = self.gcp.as_ref().ok_or_else(missing)?;
+                let verifier = GcpVerifier::new(String::new(), String::new(), gcp.audience.clone(), gcp.issuer.clone())
+                    .with_policy(gcp.claims.clone());
+                let verifier = match &gcp.jwks_file {
+                    Some(path) => verifier.with_jwks_file(path)?,
+                    None => verifier,
+                };
+                Ok(Box::new(verifier))
+            }
+            TEEType::AliCloud => {
+                let tdx = self.tdx.as_ref().ok_or_else(missing)?;
+                Ok(Box::new(AliCloudVerifier::new(TdxPolicy {
+                    mr_td: tdx.mr_td.clone(),
+                    rtmrs: tdx.rtmrs.clone(),
+                    // bound to a request by the preimage the report carries and the nonce
+                    report_data: None,
+                    allow_unbound_reports: tdx.allow_unbound_reports,
+                })))
+            }
+            TEEType::SevSnp => {
+                let sev_snp = self.sev_snp.as_ref().ok_or_else(missing)?;
+                Ok(Box::new(
+                    SevSnpVerifier::from_kds(sev_snp.product.clone(), &sev_snp.ark_sha256, sev_snp.measurement.clone()).await?,
+                ))
+            }
+            TEEType::Mock if self.allow_mock => Ok(Box::new(MockVerifier {})),
+            _ => Err(missing()),
+        }
+    }
+}
+
+/// TEE that produced a report, told from its format.
+pub fn report_type(report: &str) -> TEEType {
+    let report = report.trim();
+    if serde_json::from_str::<TdxReport>(report).is_ok() {
+        TEEType::AliCloud
+    } else if serde_json::from_str::<SevSnpReport>(report).is_ok() {
+        TEEType::SevSnp
+    } else if serde_json::from_str::<MockReport>(report).is_ok() {
+        TEEType::Mock
+    } else if report.split('.').count() == 3 {
+        TEEType::GCP
+    } else if !report.is_empty() && hex::decode(report).is_ok() {
+        // reports of older nodes are the bare hex quote
+        TEEType::AliCloud
+    } else {
+        TEEType::Unkown
+    }
+}
+
+/// Preimage of the report_data a report carries. GCP tokens only carry its digest, as the nonce.
+pub fn report_preimage(report: &str) -> Result<Option<ReportData>, VerificationError> {
+    let preimage = match report_type(report) {
+        TEEType::AliCloud => serde_json::from_str::<TdxReport>(report).map(|r| r.report_data).unwrap_or_default(),
+        TEEType::SevSnp => serde_json::from_str::<SevSnpReport>(report).map(|r| r.report_data).unwrap_or_default(),
+        TEEType::Mock => serde_json::from_str::<MockReport>(report).map(|r| r.report_data).unwrap_or_default(),
+        _ => String::new(),
+    };
+    if preimage.is_empty() {
+        return Ok(None);
+    }
+    let preimage = hex::decode(&preimage).map_err(|_| VerificationError::DecodeError)?;
+    decode(&preimage).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_policy() {
+        let policy = Policy::from_str(
+            r#"
+allow_mock: true
+gcp:
+  audience: https://sts.googleapis.com
+  claims:
+    image_digests: ["sha256:01"]
+    secboot: true
+tdx:
+  rtmrs: [null, null, null, "00"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(policy.gcp.as_ref().unwrap().issuer, EXPECTED_ISSUER);
+        assert_eq!(policy.tdx.as_ref().unwrap().rtmrs[3].as_deref(), Some("00"));
+        assert!(policy.verifier(TEEType::GCP).await.is_ok());
+        assert!(policy.verifier(TEEType::Mock).await.is_ok());
+        assert!(policy.verifier(TEEType::SevSnp).await.is_err());
+        assert!(Policy::from_str("tdx: {mrtd: '00'}").is_err());
+        // the ARK has to be pinned
+        assert!(Policy::from_str("sev_snp: {product: Milan}").is_err());
+    }
+
+    #[test]
+    fn test_report_type() {
+        let report_data = ReportData {
+            network: "aizel".to_string(),
+            request_id: 3,
+            ..Default::default()
+        };
+        let mock = serde_json::to_string(&MockReport {
+            report_data: hex::encode(report_data.encode()),
+            signature: String::new(),
+        })
+        .unwrap();
+        assert_eq!(report_type(&mock), TEEType::Mock);
+        assert_eq!(report_preimage(&mock).unwrap(), Some(report_data));
+
+        let tdx = serde_json::to_string(&TdxReport {
+            quote: "00".to_string(),
+            event_log: vec![],
+            report_data: String::new(),
+        })
+        .unwrap();
+        assert_eq!(report_type(&tdx), TEEType::AliCloud);
+        assert_eq!(report_preimage(&tdx).unwrap(), None);
+        assert_eq!(report_type("0400"), TEEType::AliCloud);
+        assert_eq!(report_type("eyJhbGciOiJSUzI1NiJ9.eyJhdWQiOiJhIn0.c2ln"), TEEType::GCP);
+        assert_eq!(report_type("mock report"), TEEType::Unkown);
+    }
+}
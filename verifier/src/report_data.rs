@@ -0,0 +1,129 @@
+use common::error::VerificationError;
+use common::tee::report_data::{ReportData, REPORT_DATA_VERSION};
+use log::warn;
+
+fn report_data_error(message: String) -> VerificationError {
+    VerificationError::ReportDataError { message }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VerificationError> {
+        if self.buf.len() < n {
+            return Err(report_data_error("preimage is truncated".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, VerificationError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, VerificationError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], VerificationError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+/// Decodes the preimage produced by `ReportData::encode`.
+pub fn decode(preimage: &[u8]) -> Result<ReportData, VerificationError> {
+    let mut reader = Reader { buf: preimage };
+    let version = reader.take(1)?[0];
+    if version != REPORT_DATA_VERSION {
+        return Err(report_data_error(format!("unsupported version {}", version)));
+    }
+    let node_pk = reader.bytes()?.to_vec();
+    let network = String::from_utf8(reader.bytes()?.to_vec())
+        .map_err(|_| report_data_error("network is not utf8".to_string()))?;
+    let report_data = ReportData {
+        node_pk,
+        network,
+        request_id: reader.u64()?,
+        model_id: reader.u64()?,
+        input_hash: reader.take(32)?.try_into().unwrap(),
+        output_hash: reader.take(32)?.try_into().unwrap(),
+    };
+    if !reader.buf.is_empty() {
+        return Err(report_data_error(format!("{} trailing bytes", reader.buf.len())));
+    }
+    Ok(report_data)
+}
+
+/// Decodes the preimage after checking it is the one the TDX or SEV-SNP report_data commits to.
+pub fn decode_report_data(report_data: &[u8; 64], preimage: &[u8]) -> Result<ReportData, VerificationError> {
+    let decoded = decode(preimage)?;
+    if decoded.tdx_report_data() != *report_data {
+        return Err(report_data_error(format!(
+            "report_data {} doesn't match the preimage",
+            hex::encode(report_data)
+        )));
+    }
+    Ok(decoded)
+}
+
+/// Decodes the preimage after checking it is the one a GCP token nonce commits to.
+pub fn decode_nonce(nonce: &str, preimage: &[u8]) -> Result<ReportData, VerificationError> {
+    let decoded = decode(preimage)?;
+    if decoded.nonce() != nonce.to_lowercase() {
+        return Err(report_data_error(format!("nonce {} doesn't match the preimage", nonce)));
+    }
+    Ok(decoded)
+}
+
+/// Checks the nonce a report was issued for, i.e. the sha256 of its report_data preimage, is the
+/// expected one. Reports can't be replayed for another request then.
+pub fn check_nonce(expect: &Option<String>, get: &[u8]) -> Result<(), VerificationError> {
+    match expect {
+        Some(expect) if hex::encode(get) != expect.to_lowercase() => Err(VerificationError::GoldenValueMismatchError {
+            value: "nonce".to_string(),
+            expect: expect.clone(),
+            get: hex::encode(get),
+        }),
+        Some(_) => Ok(()),
+        None => {
+            warn!("nonce {} of the report not checked", hex::encode(get));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let report_data = ReportData {
+            node_pk: vec![2; 33],
+            network: "aizel".to_string(),
+            request_id: 7,
+            model_id: 3,
+            input_hash: [1; 32],
+            output_hash: [9; 32],
+        };
+        let preimage = report_data.encode();
+        assert_eq!(decode(&preimage).unwrap(), report_data);
+        assert_eq!(decode_report_data(&report_data.tdx_report_data(), &preimage).unwrap(), report_data);
+        assert_eq!(decode_nonce(&report_data.nonce(), &preimage).unwrap(), report_data);
+
+        let other = ReportData {
+            request_id: 8,
+            ..report_data.clone()
+        };
+        assert!(decode_report_data(&other.tdx_report_data(), &preimage).is_err());
+        assert!(decode(&preimage[..preimage.len() - 1]).is_err());
+
+        assert!(check_nonce(&Some(report_data.nonce().to_uppercase()), &report_data.digest()).is_ok());
+        assert!(check_nonce(&Some(other.nonce()), &report_data.digest()).is_err());
+        assert!(check_nonce(&None, &report_data.digest()).is_ok());
+    }
+}
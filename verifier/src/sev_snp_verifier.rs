@@ -0,0 +1,456 @@
+use super::report_data::{check_nonce, decode_report_data};
+use async_trait::async_trait;
+use common::error::{Error, VerificationError};
+use common::tee::sev_snp::SevSnpReport;
+use common::tee::{verifier::TEEVerifier, TEEType, TEEType::SevSnp};
+use log::{info, warn};
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::sha::sha384;
+use openssl::x509::X509;
+use reqwest::Client;
+
+const KDS_URL: &str = "https://kdsintf.amd.com/vcek/v1";
+pub const REPORT_SIZE: usize = 0x4A0;
+// the signature covers the report up to the signature field
+const SIGNED_SIZE: usize = 0x2A0;
+// r and s are little endian and zero padded to 72 bytes
+const SIGNATURE_COMPONENT_SIZE: usize = 72;
+const P384_SIZE: usize = 48;
+// ECDSA P-384 with SHA-384
+const SIGNATURE_ALGO_ECDSA_P384: u32 = 1;
+// guest policy bit allowing the hypervisor to debug the guest, and read its memory
+const POLICY_DEBUG: u64 = 1 << 19;
+
+fn snp_error(message: String) -> Error {
+    Error::VerificationError {
+        teetype: SevSnp,
+        error: VerificationError::SevSnpVerificationError { message },
+    }
+}
+
+/// Security version numbers of the firmware components, used to fetch the matching VCEK.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TcbVersion {
+    pub boot_loader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl TcbVersion {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        TcbVersion {
+            boot_loader: bytes[0],
+            tee: bytes[1],
+            snp: bytes[6],
+            microcode: bytes[7],
+        }
+    }
+}
+
+/// Fields of an SEV-SNP attestation report the verifier checks.
+#[derive(Debug, Clone)]
+pub struct AttestationReport {
+    pub version: u32,
+    pub guest_svn: u32,
+    pub policy: u64,
+    pub vmpl: u32,
+    pub signature_algo: u32,
+    pub report_data: [u8; 64],
+    pub measurement: [u8; 48],
+    pub reported_tcb: TcbVersion,
+    pub chip_id: [u8; 64],
+    // big endian, as openssl expects them
+    pub signature_r: Vec<u8>,
+    pub signature_s: Vec<u8>,
+    signed: Vec<u8>,
+}
+
+fn component(bytes: &[u8]) -> Vec<u8> {
+    let mut component = bytes[..P384_SIZE].to_vec();
+    component.reverse();
+    component
+}
+
+impl AttestationReport {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < REPORT_SIZE {
+            return Err(snp_error(format!("report is {} bytes, expect {}", bytes.len(), REPORT_SIZE)));
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let signature = &bytes[SIGNED_SIZE..];
+        Ok(AttestationReport {
+            version: u32_at(0x00),
+            guest_svn: u32_at(0x04),
+            policy: u64::from_le_bytes(bytes[0x08..0x10].try_into().unwrap()),
+            vmpl: u32_at(0x30),
+            signature_algo: u32_at(0x34),
+            report_data: bytes[0x50..0x90].try_into().unwrap(),
+            measurement: bytes[0x90..0xC0].try_into().unwrap(),
+            reported_tcb: TcbVersion::from_bytes(&bytes[0x180..0x188]),
+            chip_id: bytes[0x1A0..0x1E0].try_into().unwrap(),
+            signature_r: component(signature),
+            signature_s: component(&signature[SIGNATURE_COMPONENT_SIZE..]),
+            signed: bytes[..SIGNED_SIZE].to_vec(),
+        })
+    }
+
+    /// Checks the report is signed by the VCEK.
+    pub fn verify_signature(&self, vcek: &X509) -> Result<(), Error> {
+        if self.signature_algo != SIGNATURE_ALGO_ECDSA_P384 {
+            return Err(snp_error(format!("unsupported signature algorithm {}", self.signature_algo)));
+        }
+        let openssl_error = |e: openssl::error::ErrorStack| snp_error(e.to_string());
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&self.signature_r).map_err(openssl_error)?,
+            BigNum::from_slice(&self.signature_s).map_err(openssl_error)?,
+        )
+        .map_err(openssl_error)?;
+        let key = vcek.public_key().and_then(|k| k.ec_key()).map_err(openssl_error)?;
+        if !signature.verify(&sha384(&self.signed), &key).map_err(openssl_error)? {
+            return Err(snp_error("report signature doesn't match the VCEK".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Verifies SEV-SNP reports: the VCEK has to chain up to the trusted AMD root key (ARK) through
+/// the AMD signing key (ASK), sign the report, the guest must not be debuggable and the launch
+/// measurement has to be the expected one.
+#[derive(Debug)]
+pub struct SevSnpVerifier {
+    // Milan, Genoa, ...
+    product: String,
+    ark: X509,
+    ask: X509,
+    // hex encoded launch measurement, reports are rejected if not set unless the check is skipped
+    measurement: Option<String>,
+}
+
+/// Checks the ARK is the one of `product` the policy pins by its hex sha256 fingerprint, the key
+/// distribution service is only trusted for the ASK and the VCEKs.
+pub fn pin_ark(ark: &X509, product: &str, fingerprint: &str) -> Result<(), Error> {
+    let digest = ark.digest(MessageDigest::sha256()).map_err(|e| snp_error(e.to_string()))?;
+    let get = hex::encode(digest);
+    let expect = fingerprint.replace(':', "").to_lowercase();
+    if get != expect {
+        return Err(Error::VerificationError {
+            teetype: SevSnp,
+            error: VerificationError::GoldenValueMismatchError {
+                value: format!("ARK of {}", product),
+                expect,
+                get,
+            },
+        });
+    }
+    Ok(())
+}
+
+impl SevSnpVerifier {
+    pub fn new(product: String, ark: X509, ask: X509, measurement: Option<String>) -> Self {
+        SevSnpVerifier {
+            product,
+            ark,
+            ask,
+            measurement,
+        }
+    }
+
+    /// Creates a verifier with the ARK and ASK of `product` served by the AMD key distribution service,
+    /// the ARK has to match the pinned `ark_fingerprint`.
+    pub async fn from_kds(product: String, ark_fingerprint: &str, measurement: Option<String>) -> Result<Self, Error> {
+        let url = format!("{}/{}/cert_chain", KDS_URL, product);
+        let pem = Self::fetch(&url).await?;
+        // the chain is the ASK followed by the ARK
+        let mut chain = X509::stack_from_pem(&pem).map_err(|e| snp_error(e.to_string()))?;
+        if chain.len() != 2 {
+            return Err(snp_error(format!("cert chain of {} has {} certificates", product, chain.len())));
+        }
+        let ark = chain.pop().unwrap();
+        let ask = chain.pop().unwrap();
+        pin_ark(&ark, &product, ark_fingerprint)?;
+        Ok(Self::new(product, ark, ask, measurement))
+    }
+
+    async fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+        let network_error = |e: reqwest::Error| Error::NetworkError {
+            address: url.to_string(),
+            message: e.to_string(),
+        };
+        let response = Client::new()
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(network_error)?;
+        Ok(response.bytes().await.map_err(network_error)?.to_vec())
+    }
+
+    async fn vcek(&self, report: &AttestationReport, vcek: &str) -> Result<X509, Error> {
+        let der = if vcek.is_empty() {
+            let tcb = report.reported_tcb;
+            let url = format!(
+                "{}/{}/{}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+                KDS_URL,
+                self.product,
+                hex::encode(report.chip_id),
+                tcb.boot_loader,
+                tcb.tee,
+                tcb.snp,
+                tcb.microcode
+            );
+            Self::fetch(&url).await?
+        } else {
+            hex::decode(vcek).map_err(|_| Error::VerificationError {
+                teetype: SevSnp,
+                error: VerificationError::DecodeError,
+            })?
+        };
+        X509::from_der(&der).map_err(|e| snp_error(e.to_string()))
+    }
+
+    /// Checks the ARK is self signed and signs the ASK, which signs the VCEK.
+    pub fn verify_chain(&self, vcek: &X509) -> Result<(), Error> {
+        let openssl_error = |e: openssl::error::ErrorStack| snp_error(e.to_string());
+        let ark_key = self.ark.public_key().map_err(openssl_error)?;
+        let ask_key = self.ask.public_key().map_err(openssl_error)?;
+        for (cert, issuer, name) in [(&self.ark, &ark_key, "ARK"), (&self.ask, &ark_key, "ASK"), (vcek, &ask_key, "VCEK")] {
+            if !cert.verify(issuer).map_err(openssl_error)? {
+                return Err(snp_error(format!("{} signature is invalid", name)));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn verify_report(&self, report: &SevSnpReport, skip_verify_measurement: bool) -> Result<AttestationReport, Error> {
+        let decode_error = || Error::VerificationError {
+            teetype: SevSnp,
+            error: VerificationError::DecodeError,
+        };
+        let bytes = hex::decode(&report.report).map_err(|_| decode_error())?;
+        let parsed = AttestationReport::parse(&bytes)?;
+        let vcek = self.vcek(&parsed, &report.vcek).await?;
+        self.verify_chain(&vcek)?;
+        parsed.verify_signature(&vcek)?;
+
+        if parsed.vmpl != 0 {
+            return Err(snp_error(format!("report requested from vmpl {}", parsed.vmpl)));
+        }
+        if parsed.policy & POLICY_DEBUG != 0 {
+            return Err(snp_error(format!("guest policy {:#x} allows debugging", parsed.policy)));
+        }
+        match &self.measurement {
+            Some(expect) if !skip_verify_measurement => {
+                let get = hex::encode(parsed.measurement);
+                if get != expect.to_lowercase() {
+                    return Err(Error::VerificationError {
+                        teetype: SevSnp,
+                        error: VerificationError::GoldenValueMismatchError {
+                            value: "measurement".to_string(),
+                            expect: expect.clone(),
+                            get,
+                        },
+                    });
+                }
+            }
+            Some(_) => warn!("launch measurement {} not checked", hex::encode(parsed.measurement)),
+            None if skip_verify_measurement => {
+                warn!("launch measurement {} not checked", hex::encode(parsed.measurement))
+            }
+            None => {
+                return Err(Error::VerificationError {
+                    teetype: SevSnp,
+                    error: VerificationError::PolicyViolationError {
+                        violations: vec!["no launch measurement is expected".to_string()],
+                    },
+                })
+            }
+        }
+
+        let preimage = hex::decode(&report.report_data).map_err(|_| decode_error())?;
+        let report_data = decode_report_data(&parsed.report_data, &preimage).map_err(|e| Error::VerificationError {
+            teetype: SevSnp,
+            error: e,
+        })?;
+        info!(
+            "report of node {} for request {} of network {}, model {}",
+            hex::encode(&report_data.node_pk), report_data.request_id, report_data.network, report_data.model_id
+        );
+        Ok(parsed)
+    }
+}
+
+#[async_trait]
+impl TEEVerifier for SevSnpVerifier {
+    async fn verify(&self, report: String, nonce: Option<String>, skip_verify_image_digest: bool) -> Result<bool, Error> {
+        let report: SevSnpReport = serde_json::from_str(&report).map_err(|_| Error::VerificationError {
+            teetype: SevSnp,
+            error: VerificationError::DecodeError,
+        })?;
+        let parsed = self.verify_report(&report, skip_verify_image_digest).await?;
+        check_nonce(&nonce, &parsed.report_data[..32]).map_err(|e| Error::VerificationError {
+            teetype: SevSnp,
+            error: e,
+        })?;
+        Ok(true)
+    }
+
+    fn get_type(&self) -> Result<TEEType, Error> {
+        Ok(TEEType::SevSnp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tee::report_data::ReportData;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+
+    const MEASUREMENT: [u8; 48] = [0xAB; 48];
+
+    fn cert(name: &str, key: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let (issuer_name, signer) = match issuer {
+            Some((issuer, signer)) => (issuer.subject_name(), signer),
+            None => (subject.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        builder.sign(signer, MessageDigest::sha384()).unwrap();
+        builder.build()
+    }
+
+    struct Fixture {
+        verifier: SevSnpVerifier,
+        vcek: X509,
+        vcek_key: EcKey<Private>,
+    }
+
+    /// A stand-in for the AMD chain: RSA ARK and ASK, P-384 VCEK.
+    fn fixture(measurement: Option<String>) -> Fixture {
+        let ark_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let ask_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let vcek_key = EcKey::generate(&EcGroup::from_curve_name(Nid::SECP384R1).unwrap()).unwrap();
+        let ark = cert("ARK-Milan", &ark_key, None);
+        let ask = cert("SEV-Milan", &ask_key, Some((&ark, &ark_key)));
+        let vcek = cert("SEV-VCEK", &PKey::from_ec_key(vcek_key.clone()).unwrap(), Some((&ask, &ask_key)));
+        Fixture {
+            verifier: SevSnpVerifier::new("Milan".to_string(), ark, ask, measurement),
+            vcek,
+            vcek_key,
+        }
+    }
+
+    fn report(fixture: &Fixture, report_data: &ReportData) -> SevSnpReport {
+        report_with_policy(fixture, report_data, 0x30000)
+    }
+
+    fn report_with_policy(fixture: &Fixture, report_data: &ReportData, policy: u64) -> SevSnpReport {
+        let mut bytes = vec![0u8; REPORT_SIZE];
+        bytes[0x00..0x04].copy_from_slice(&2u32.to_le_bytes());
+        bytes[0x08..0x10].copy_from_slice(&policy.to_le_bytes());
+        bytes[0x34..0x38].copy_from_slice(&SIGNATURE_ALGO_ECDSA_P384.to_le_bytes());
+        bytes[0x50..0x90].copy_from_slice(&report_data.tdx_report_data());
+        bytes[0x90..0xC0].copy_from_slice(&MEASUREMENT);
+        let signature = EcdsaSig::sign(&sha384(&bytes[..SIGNED_SIZE]), &fixture.vcek_key).unwrap();
+        for (offset, component) in [(SIGNED_SIZE, signature.r()), (SIGNED_SIZE + SIGNATURE_COMPONENT_SIZE, signature.s())] {
+            let mut le = component.to_vec_padded(P384_SIZE as i32).unwrap();
+            le.reverse();
+            bytes[offset..offset + P384_SIZE].copy_from_slice(&le);
+        }
+        SevSnpReport {
+            report: hex::encode(bytes),
+            report_data: hex::encode(report_data.encode()),
+            vcek: hex::encode(fixture.vcek.to_der().unwrap()),
+        }
+    }
+
+    fn report_data() -> ReportData {
+        ReportData {
+            node_pk: vec![2; 33],
+            network: "aizel".to_string(),
+            request_id: 1,
+            model_id: 2,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_report() {
+        let fixture = fixture(Some(hex::encode(MEASUREMENT)));
+        let report = report(&fixture, &report_data());
+        let parsed = fixture.verifier.verify_report(&report, false).await.unwrap();
+        assert_eq!(parsed.version, 2);
+        assert_eq!(parsed.measurement, MEASUREMENT);
+
+        // a byte flipped in the signed part
+        let mut tampered = report.clone();
+        tampered.report.replace_range(0x100..0x102, "ff");
+        assert!(fixture.verifier.verify_report(&tampered, false).await.is_err());
+
+        // preimage of another request
+        let mut other = report.clone();
+        other.report_data = hex::encode(ReportData { request_id: 9, ..report_data() }.encode());
+        assert!(fixture.verifier.verify_report(&other, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_measurement_policy() {
+        let fixture = fixture(Some(hex::encode([0xCD; 48])));
+        let report = report(&fixture, &report_data());
+        assert!(matches!(
+            fixture.verifier.verify_report(&report, false).await,
+            Err(Error::VerificationError {
+                error: VerificationError::GoldenValueMismatchError { .. },
+                ..
+            })
+        ));
+        assert!(fixture.verifier.verify_report(&report, true).await.is_ok());
+
+        // no measurement expected
+        let fixture = self::fixture(None);
+        let report = self::report(&fixture, &report_data());
+        assert!(fixture.verifier.verify_report(&report, false).await.is_err());
+        assert!(fixture.verifier.verify_report(&report, true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_debug_policy() {
+        let fixture = fixture(Some(hex::encode(MEASUREMENT)));
+        let report = report_with_policy(&fixture, &report_data(), 0x30000 | POLICY_DEBUG);
+        assert!(fixture.verifier.verify_report(&report, false).await.is_err());
+        assert!(fixture.verifier.verify_report(&report, true).await.is_err());
+    }
+
+    #[test]
+    fn test_pin_ark() {
+        let fixture = fixture(None);
+        let fingerprint = hex::encode(fixture.verifier.ark.digest(MessageDigest::sha256()).unwrap());
+        assert!(pin_ark(&fixture.verifier.ark, "Milan", &fingerprint).is_ok());
+        assert!(pin_ark(&fixture.verifier.ark, "Milan", &fingerprint.to_uppercase()).is_ok());
+        assert!(pin_ark(&fixture.verifier.ark, "Milan", &"00".repeat(32)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_chain() {
+        let fixture = fixture(None);
+        let report = report(&fixture, &report_data());
+        // VCEK of another chain
+        let other = self::fixture(None);
+        assert!(other.verifier.verify_report(&report, false).await.is_err());
+    }
+}
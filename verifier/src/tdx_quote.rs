@@ -0,0 +1,224 @@
+use common::error::VerificationError;
+use common::tee::event_log::Rtmr;
+use log::warn;
+
+pub const QUOTE_VERSION: u16 = 4;
+pub const TEE_TYPE_TDX: u32 = 0x81;
+pub const HEADER_SIZE: usize = 48;
+pub const TD_REPORT_SIZE: usize = 584;
+
+fn decode_error(message: String) -> VerificationError {
+    VerificationError::TDXVerificationError { message }
+}
+
+fn mismatch(value: &str, expect: &str, get: &[u8]) -> VerificationError {
+    VerificationError::GoldenValueMismatchError {
+        value: value.to_string(),
+        expect: expect.to_string(),
+        get: hex::encode(get),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteHeader {
+    pub version: u16,
+    pub att_key_type: u16,
+    pub tee_type: u32,
+    pub qe_vendor_id: [u8; 16],
+    pub user_data: [u8; 20],
+}
+
+/// TD report the quote is signed over, measurements of the TDX module and of the TD.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TdReportBody {
+    pub tee_tcb_svn: [u8; 16],
+    pub mr_seam: [u8; 48],
+    pub mr_signer_seam: [u8; 48],
+    pub seam_attributes: [u8; 8],
+    pub td_attributes: [u8; 8],
+    pub xfam: [u8; 8],
+    pub mr_td: [u8; 48],
+    pub mr_config_id: [u8; 48],
+    pub mr_owner: [u8; 48],
+    pub mr_owner_config: [u8; 48],
+    pub rtmrs: [Rtmr; 4],
+    pub report_data: [u8; 64],
+}
+
+/// TDX quote v4: header, TD report and the quote signature data checked by DCAP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub header: QuoteHeader,
+    pub body: TdReportBody,
+    pub signature_data: Vec<u8>,
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], VerificationError> {
+        if self.buf.len() < n {
+            return Err(decode_error("quote is truncated".to_string()));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], VerificationError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> Result<u16, VerificationError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, VerificationError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+impl Quote {
+    pub fn parse(quote: &[u8]) -> Result<Self, VerificationError> {
+        let mut reader = Reader { buf: quote };
+        let version = reader.u16()?;
+        let att_key_type = reader.u16()?;
+        let tee_type = reader.u32()?;
+        if version != QUOTE_VERSION || tee_type != TEE_TYPE_TDX {
+            return Err(decode_error(format!(
+                "unsupported quote version {} tee type {:#x}",
+                version, tee_type
+            )));
+        }
+        reader.take(4)?;
+        let header = QuoteHeader {
+            version,
+            att_key_type,
+            tee_type,
+            qe_vendor_id: reader.array()?,
+            user_data: reader.array()?,
+        };
+        let body = TdReportBody {
+            tee_tcb_svn: reader.array()?,
+            mr_seam: reader.array()?,
+            mr_signer_seam: reader.array()?,
+            seam_attributes: reader.array()?,
+            td_attributes: reader.array()?,
+            xfam: reader.array()?,
+            mr_td: reader.array()?,
+            mr_config_id: reader.array()?,
+            mr_owner: reader.array()?,
+            mr_owner_config: reader.array()?,
+            rtmrs: [reader.array()?, reader.array()?, reader.array()?, reader.array()?],
+            report_data: reader.array()?,
+        };
+        let signature_size = reader.u32()? as usize;
+        let signature_data = reader.take(signature_size)?.to_vec();
+        Ok(Quote {
+            header,
+            body,
+            signature_data,
+        })
+    }
+}
+
+/// Golden values a TD report has to match, hex encoded. Unset values aren't checked.
+#[derive(Debug, Clone, Default)]
+pub struct TdxPolicy {
+    pub mr_td: Option<String>,
+    pub rtmrs: [Option<String>; 4],
+    pub report_data: Option<[u8; 64]>,
+    // accept quotes of older nodes that carry no report_data preimage, they aren't bound to a request
+    pub allow_unbound_reports: bool,
+}
+
+impl TdxPolicy {
+    /// Checks the TD report against the policy. `skip_verify_image_digest` skips the MRTD and RTMRs,
+    /// which change with every build of the image, but never the report_data.
+    pub fn check(&self, body: &TdReportBody, skip_verify_image_digest: bool) -> Result<(), VerificationError> {
+        if let Some(expect) = &self.report_data {
+            if *expect != body.report_data {
+                return Err(mismatch("report_data", &hex::encode(expect), &body.report_data));
+            }
+        }
+        if skip_verify_image_digest {
+            warn!("mrtd and rtmrs of the quote not checked");
+            return Ok(());
+        }
+        if let Some(expect) = &self.mr_td {
+            if hex::encode(body.mr_td) != expect.to_lowercase() {
+                return Err(mismatch("mrtd", expect, &body.mr_td));
+            }
+        }
+        for (index, (expect, rtmr)) in self.rtmrs.iter().zip(body.rtmrs.iter()).enumerate() {
+            if let Some(expect) = expect {
+                if hex::encode(rtmr) != expect.to_lowercase() {
+                    return Err(mismatch(&format!("rtmr{}", index), expect, rtmr));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::tee::event_log::RTMR_SIZE;
+
+    fn rtmr_hex(byte: u8) -> String {
+        hex::encode([byte; RTMR_SIZE])
+    }
+
+    fn fixture() -> Vec<u8> {
+        hex::decode(include_str!("../tests/fixtures/tdx_quote_v4.hex").trim()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_quote() {
+        let quote = Quote::parse(&fixture()).unwrap();
+        assert_eq!(quote.header.version, 4);
+        assert_eq!(quote.header.tee_type, TEE_TYPE_TDX);
+        assert_eq!(quote.body.mr_td, [0x11; 48]);
+        assert_eq!(quote.body.rtmrs[3], [0x23; 48]);
+        assert_eq!(quote.body.report_data[63], 63);
+        assert_eq!(quote.signature_data.len(), 138);
+
+        assert!(Quote::parse(&fixture()[..HEADER_SIZE + TD_REPORT_SIZE]).is_err());
+        let mut sgx = fixture();
+        sgx[4] = 0;
+        assert!(Quote::parse(&sgx).is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let quote = Quote::parse(&fixture()).unwrap();
+        let mut report_data = [0; 64];
+        report_data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let policy = TdxPolicy {
+            mr_td: Some(hex::encode([0x11; 48])),
+            rtmrs: [None, None, Some(rtmr_hex(0x22)), None],
+            report_data: Some(report_data),
+            allow_unbound_reports: false,
+        };
+        assert!(policy.check(&quote.body, false).is_ok());
+
+        let policy = TdxPolicy {
+            rtmrs: [Some(rtmr_hex(0x00)), None, None, None],
+            ..policy
+        };
+        assert!(matches!(
+            policy.check(&quote.body, false),
+            Err(VerificationError::GoldenValueMismatchError { value, .. }) if value == "rtmr0"
+        ));
+        assert!(policy.check(&quote.body, true).is_ok());
+
+        let policy = TdxPolicy {
+            report_data: Some([0; 64]),
+            ..Default::default()
+        };
+        assert!(policy.check(&quote.body, true).is_err());
+    }
+}